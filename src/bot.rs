@@ -0,0 +1,2061 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use base64::Engine;
+use serenity::async_trait;
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use crate::config::{Config, DmMode, PresenceMode};
+use crate::docs::{build_index, DocIndex};
+use crate::gemini::{Gemini, SUPPORTED_IMAGE_TYPES};
+use crate::generation::parse_flags;
+use crate::knowledge::{new_entry, parse_note, KbEntry, KnowledgeBase};
+use crate::memory::{MemoryStore, RememberFact};
+use crate::moderation::{classify, jump_link, RateLimiter};
+use crate::persona::Personas;
+use crate::settings::{BotThread, Settings};
+use crate::structs::*;
+use crate::summarize::{format_history, parse_range, summarize, HistoryMessage};
+use crate::tools::{ToolContext, ToolRegistry};
+use crate::transcript::{export, parse_transcript, Format};
+use crate::translate::{flag_language, translate};
+use crate::usage::{UsageKey, UsageStore, MODERATION_USER};
+
+pub const OWNER_ID: u64 = 202850246261211136;
+
+// turns shown per page of !history, and how much of each
+const HISTORY_PAGE_SIZE: usize = 10;
+const HISTORY_PREVIEW_CHARS: usize = 100;
+
+#[derive(Debug, Clone, Default)]
+pub struct Attachment {
+    pub url: String,
+    pub filename: String,
+    pub content_type: Option<String>,
+}
+
+// a message the incoming message replies to
+#[derive(Debug, Clone, Default)]
+pub struct QuotedMessage {
+    pub message_id: u64,
+    pub author_id: u64,
+    pub author_name: String,
+    pub content: String,
+    pub attachments: Vec<Attachment>,
+}
+
+// a message as the bot sees it, independent of where it came from
+#[derive(Debug, Clone, Default)]
+pub struct IncomingMessage {
+    // 0 if the message has no id, like a button press
+    pub message_id: u64,
+    pub author_id: u64,
+    pub channel_id: u64,
+    // None in direct messages
+    pub guild_id: Option<u64>,
+    pub content: String,
+    pub bot_id: u64,
+    pub mentions_bot: bool,
+    pub attachments: Vec<Attachment>,
+    // the reply chain, starting with the message this one replies to, discord only
+    // includes the first one and the rest is fetched with Transport::reply_chain
+    pub referenced: Vec<QuotedMessage>,
+    // typed by the operator in the terminal, direct message limits don't apply
+    pub local: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ButtonAction {
+    Regenerate,
+    // asks gemini to go on after a reply was cut off by the token limit
+    Continue,
+    Delete,
+}
+
+// a button under the last chunk of a reply, only the person who asked or an admin can use it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Button {
+    pub action: ButtonAction,
+    // the conversation's reply count when the reply was sent
+    pub reply: u64,
+    pub user_id: u64,
+}
+
+impl Button {
+    pub fn label(&self) -> &'static str {
+        match self.action {
+            ButtonAction::Regenerate => "Regenerate",
+            ButtonAction::Continue => "Continue",
+            ButtonAction::Delete => "Delete",
+        }
+    }
+
+    // "gemini:regenerate:3:42", discord sends it back when the button is pressed
+    pub fn custom_id(&self) -> String {
+        format!(
+            "gemini:{}:{}:{}",
+            self.label().to_lowercase(),
+            self.reply,
+            self.user_id
+        )
+    }
+
+    pub fn parse(custom_id: &str) -> Option<Button> {
+        let mut parts = custom_id.strip_prefix("gemini:")?.split(':');
+        let action = match parts.next()? {
+            "regenerate" => ButtonAction::Regenerate,
+            "continue" => ButtonAction::Continue,
+            "delete" => ButtonAction::Delete,
+            _ => return None,
+        };
+        Some(Button {
+            action,
+            reply: parts.next()?.parse().ok()?,
+            user_id: parts.next()?.parse().ok()?,
+        })
+    }
+}
+
+// how the bot talks back, created for each incoming message
+#[async_trait]
+pub trait Transport: Send + Sync {
+    // replies to the incoming message, returns the id of the reply if the transport has ids
+    async fn reply(&self, text: &str) -> Option<u64>;
+    // sends a message to the incoming message's channel
+    async fn say(&self, text: &str);
+    async fn typing(&self);
+    async fn set_presence(&self, status: &str);
+    async fn download(&self, attachment: &Attachment) -> Result<Vec<u8>, String>;
+    // starts a thread from the incoming message, returns its id and a transport that posts in it
+    async fn create_thread(&self, _name: &str) -> Result<(u64, Box<dyn Transport>), String> {
+        Err(String::from("Threads are not supported here"))
+    }
+    // replies with buttons under the message, transports without buttons just reply
+    async fn reply_with_buttons(&self, text: &str, _buttons: &[Button]) -> Option<u64> {
+        self.reply(text).await
+    }
+    // deletes the message whose button was pressed
+    async fn delete_message(&self) {}
+    // edits one of the bot's replies, transports that can't edit reply again
+    async fn edit(&self, _message_id: u64, text: &str, _buttons: &[Button]) {
+        self.reply(text).await;
+    }
+    // deletes one of the bot's replies
+    async fn delete(&self, _message_id: u64) {}
+    // the channel's latest messages, oldest first, stops at messages older than since
+    async fn history(
+        &self,
+        _limit: usize,
+        _since: Option<i64>,
+    ) -> Result<Vec<HistoryMessage>, String> {
+        Err(String::from("Channel history is not available here"))
+    }
+    // posts in another channel without pinging anyone
+    async fn send_to(&self, _channel_id: u64, _text: &str) {}
+    // the channel's pinned messages
+    async fn pins(&self) -> Result<Vec<HistoryMessage>, String> {
+        Err(String::from("Pinned messages are not available here"))
+    }
+    // the incoming message's reply chain up to depth messages, only fetched when the bot answers
+    async fn reply_chain(&self, _depth: usize) -> Vec<QuotedMessage> {
+        Vec::new()
+    }
+    // replies with a file attached
+    async fn send_file(
+        &self,
+        _text: &str,
+        _filename: &str,
+        _content: Vec<u8>,
+    ) -> Result<(), String> {
+        Err(String::from("Files can't be sent here"))
+    }
+}
+
+pub struct Bot {
+    pub gemini: Gemini,
+    pub personas: Personas,
+    // conversation history with gemini, one per channel
+    pub conversations: Mutex<HashMap<u64, Arc<Mutex<Conversation>>>>,
+    pub config: Config,
+    pub usage: Mutex<UsageStore>,
+    pub settings: Mutex<Settings>,
+    // shared with the tool gemini saves facts with
+    pub memory: Arc<Mutex<MemoryStore>>,
+    pub docs: Mutex<DocIndex>,
+    pub knowledge: Mutex<KnowledgeBase>,
+    // functions gemini can call, transports register their own before the bot starts
+    pub tools: ToolRegistry,
+    // limits how many messages moderation classifies
+    pub moderation_limits: Mutex<RateLimiter>,
+}
+
+// returns the argument if the message is the command, "!usage month" -> Some("month")
+pub fn command<'a>(content: &'a str, name: &str) -> Option<&'a str> {
+    let rest = content.strip_prefix(name)?;
+    if rest.is_empty() || rest.starts_with(' ') {
+        Some(rest.trim())
+    } else {
+        None
+    }
+}
+
+impl Bot {
+    // stores are loaded from the files in the config, empty paths keep them in memory
+    pub fn new(gemini: Gemini, personas: Personas, config: Config) -> Self {
+        let memory = Arc::new(Mutex::new(MemoryStore::load(&config.memory_file)));
+        let mut tools =
+            ToolRegistry::with_builtins(config.disabled_tools.clone(), config.max_tool_steps);
+        tools.register(RememberFact {
+            store: memory.clone(),
+        });
+        Bot {
+            gemini,
+            personas,
+            conversations: Mutex::new(HashMap::new()),
+            usage: Mutex::new(UsageStore::load(&config.usage_file)),
+            settings: Mutex::new(Settings::load(&config.settings_file)),
+            memory,
+            docs: Mutex::new(DocIndex::load(&config.docs_index_file)),
+            knowledge: Mutex::new(KnowledgeBase::load(&config.kb_file)),
+            tools,
+            moderation_limits: Mutex::new(RateLimiter::new(config.moderation_per_minute)),
+            config,
+        }
+    }
+
+    pub async fn get_conversation(&self, channel_id: u64) -> Arc<Mutex<Conversation>> {
+        let mut local_conversations = self.conversations.lock().await;
+        local_conversations
+            .entry(channel_id)
+            .or_insert_with(|| {
+                let mut conversation = Conversation::default();
+                if let Some(persona) = self.personas.get("default") {
+                    conversation.set_persona(&persona.name, &persona.system_instruction);
+                }
+                Arc::new(Mutex::new(conversation))
+            })
+            .clone()
+    }
+
+    pub async fn reset_conversation(&self, channel_id: u64) {
+        info!("Reseting history...");
+        let conversation = self.get_conversation(channel_id).await;
+        let mut local_conversation = conversation.lock().await;
+        local_conversation.reset_conversation();
+    }
+
+    // returns the reply, its token usage and the model that answered
+    pub async fn send_msg_to_gemini(
+        &self,
+        msg: &IncomingMessage,
+        message: String,
+        images: Vec<InlineData>,
+        overrides: &GenerationConfig,
+        mut context: Vec<String>,
+    ) -> (String, UsageMetadata, String) {
+        let channel_id = msg.channel_id;
+        let conversation = self.get_conversation(channel_id).await;
+        let mut local_conversation = conversation.lock().await;
+        let model = self
+            .pick_model(
+                channel_id,
+                !images.is_empty(),
+                local_conversation.token_count,
+            )
+            .await;
+
+        let generation = self
+            .generation_config(channel_id, msg.guild_id, &local_conversation.persona)
+            .await
+            .merge(overrides);
+        if let Err(error) = generation.validate(&model) {
+            return (error, UsageMetadata::default(), model);
+        }
+        local_conversation.generationConfig = if generation.is_empty() {
+            None
+        } else {
+            Some(generation)
+        };
+
+        let ctx = ToolContext {
+            user_id: msg.author_id,
+            channel_id,
+            guild_id: msg.guild_id,
+            is_admin: self.config.is_admin(msg.author_id),
+        };
+        // the asker's facts and the context are only added for this request,
+        // the channel's conversation is shared
+        context.extend(self.memory.lock().await.instruction(msg.author_id));
+        let added = context.len();
+        if added > 0 {
+            let instruction =
+                local_conversation
+                    .systemInstruction
+                    .get_or_insert_with(|| Contents {
+                        role: String::from("user"),
+                        ..Default::default()
+                    });
+            instruction
+                .parts
+                .extend(context.into_iter().map(|text| Parts {
+                    text,
+                    ..Default::default()
+                }));
+        }
+        // remembers the prompt so edits and deletes can find it
+        let prompt_ids = if msg.message_id == 0 {
+            Vec::new()
+        } else {
+            vec![msg.message_id]
+        };
+        let (text, usage_metadata) = self
+            .gemini
+            .send_msg_with_tools(
+                &model,
+                &mut local_conversation,
+                message,
+                images,
+                prompt_ids,
+                &self.tools,
+                &ctx,
+            )
+            .await;
+        if added > 0 {
+            if let Some(instruction) = &mut local_conversation.systemInstruction {
+                let kept = instruction.parts.len() - added;
+                instruction.parts.truncate(kept);
+                if instruction.parts.is_empty() {
+                    local_conversation.systemInstruction = None;
+                }
+            }
+        }
+        (text, usage_metadata, model)
+    }
+
+    // guild settings, overridden by channel settings, overridden by the persona
+    pub async fn generation_config(
+        &self,
+        channel_id: u64,
+        guild_id: Option<u64>,
+        persona: &str,
+    ) -> GenerationConfig {
+        let local_settings = self.settings.lock().await;
+        let guild = match guild_id {
+            Some(guild_id) => local_settings.guild(guild_id).generation,
+            None => GenerationConfig::default(),
+        };
+        let channel = local_settings.channel(channel_id).generation;
+        let persona = self
+            .personas
+            .get(persona)
+            .map(|p| p.generation.clone())
+            .unwrap_or_default();
+        guild.merge(&channel).merge(&persona)
+    }
+
+    // !gen shows the channel's generation settings,
+    // "!gen channel|guild --temp 0.2 ..." changes them and "!gen channel|guild reset" clears them
+    pub async fn configure_generation(&self, msg: &IncomingMessage, argument: &str) -> String {
+        let (scope, flags) = argument.split_once(' ').unwrap_or((argument, ""));
+        if scope.is_empty() {
+            let conversation = self.get_conversation(msg.channel_id).await;
+            let persona = conversation.lock().await.persona.clone();
+            let generation = self
+                .generation_config(msg.channel_id, msg.guild_id, &persona)
+                .await;
+            return format!("Generation settings: {}", generation.describe());
+        }
+        if !self.config.is_admin(msg.author_id) {
+            return String::from("Only admins can change generation settings");
+        }
+
+        let generation = if flags.trim() == "reset" {
+            GenerationConfig::default()
+        } else {
+            let (rest, generation) = match parse_flags(flags) {
+                Ok(parsed) => parsed,
+                Err(error) => return error,
+            };
+            if !rest.is_empty() || generation.is_empty() {
+                return String::from(
+                    "Usage: !gen channel|guild --temp 0.2 --top-p 0.9 --top-k 40 --max-tokens 1000 --stop word, or reset",
+                );
+            }
+            let model = self.pick_model(msg.channel_id, false, 0).await;
+            if let Err(error) = generation.validate(&model) {
+                return error;
+            }
+            generation
+        };
+
+        let mut local_settings = self.settings.lock().await;
+        let current = match (scope, msg.guild_id) {
+            ("channel", _) => &mut local_settings.channel_mut(msg.channel_id).generation,
+            ("guild", Some(guild_id)) => &mut local_settings.guild_mut(guild_id).generation,
+            ("guild", None) => {
+                return String::from("Guild settings can only be changed in a guild")
+            }
+            _ => return format!("Unknown scope: {}, use channel or guild", scope),
+        };
+        *current = if generation.is_empty() {
+            generation
+        } else {
+            current.merge(&generation)
+        };
+        let text = format!(
+            "Generation settings for this {}: {}",
+            scope,
+            current.describe()
+        );
+        local_settings.save();
+        text
+    }
+
+    // the channel's model if an admin set one, otherwise routes by the kind of request
+    pub async fn pick_model(&self, channel_id: u64, has_image: bool, token_count: i32) -> String {
+        if let Some(model) = self.settings.lock().await.channel(channel_id).model {
+            return model;
+        }
+        if has_image {
+            if let Some(model) = &self.config.route_multimodal_model {
+                info!("Routing multimodal request to {}", model);
+                return model.clone();
+            }
+        }
+        if token_count >= self.config.route_long_context_tokens {
+            if let Some(model) = &self.config.route_long_context_model {
+                info!("Routing long context request to {}", model);
+                return model.clone();
+            }
+        }
+        self.gemini.model.clone()
+    }
+
+    // !models lists the available models
+    pub async fn list_models(&self) -> String {
+        match self.gemini.list_models().await {
+            Ok(models) => {
+                let mut text = String::from("**Available models**\n");
+                for model in models {
+                    text.push_str(&format!(
+                        "`{}` - {} ({} input tokens)\n",
+                        model.name,
+                        model.displayName,
+                        format_tokens(model.inputTokenLimit as i64)
+                    ));
+                }
+                text
+            }
+            Err(error) => error,
+        }
+    }
+
+    // empty name shows the channel's model, "default" goes back to automatic routing
+    pub async fn set_model(&self, channel_id: u64, name: &str) -> String {
+        if name.is_empty() {
+            return match self.settings.lock().await.channel(channel_id).model {
+                Some(model) => format!("This channel uses {}", model),
+                None => format!(
+                    "This channel uses the default model ({})",
+                    self.gemini.model
+                ),
+            };
+        }
+        if name == "default" {
+            let mut local_settings = self.settings.lock().await;
+            local_settings.channel_mut(channel_id).model = None;
+            local_settings.save();
+            return format!("Switched to the default model ({})", self.gemini.model);
+        }
+
+        let name = name.trim_start_matches("models/");
+        match self.gemini.list_models().await {
+            Ok(models) if !models.iter().any(|m| m.name == name) => {
+                return format!("Unknown model: {}, see !models", name);
+            }
+            Ok(_) => {}
+            // the model might still work, the list just isn't available
+            Err(error) => error!("Could not validate model {}: {}", name, error),
+        }
+        let mut local_settings = self.settings.lock().await;
+        local_settings.channel_mut(channel_id).model = Some(name.to_string());
+        local_settings.save();
+        format!("Switched to {}", name)
+    }
+
+    // whether the user may talk to the bot in direct messages and has quota left today
+    pub async fn check_dm(&self, user_id: u64) -> Result<(), String> {
+        let allowed = self.config.is_admin(user_id)
+            || self.config.dm_mode == DmMode::Open
+            || self.settings.lock().await.dm_users.contains(&user_id);
+        if !allowed {
+            return Err(match self.config.dm_mode {
+                DmMode::OptIn => String::from("Send !dm optin to chat with me in direct messages"),
+                _ => String::from("Direct messages are limited to allowed users, ask an admin"),
+            });
+        }
+
+        if self.config.dm_daily_tokens > 0 {
+            let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+            let used = self
+                .usage
+                .lock()
+                .await
+                .report(&today, |record| {
+                    record.guild_id == 0 && record.user_id == user_id
+                })
+                .total
+                .total_tokens();
+            if used >= self.config.dm_daily_tokens {
+                return Err(format!(
+                    "You have used your daily quota of {} tokens in direct messages",
+                    format_tokens(self.config.dm_daily_tokens)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // check_dm for messages in direct messages, guild and terminal messages are always allowed
+    pub async fn check_message(&self, msg: &IncomingMessage) -> Result<(), String> {
+        if msg.guild_id.is_some() || msg.local {
+            return Ok(());
+        }
+        self.check_dm(msg.author_id).await
+    }
+
+    // "!dm optin|optout" for users when opt-in is enabled, "!dm allow|deny <id>" for admins
+    pub async fn configure_dm(&self, msg: &IncomingMessage, argument: &str) -> String {
+        let (action, target) = argument.split_once(' ').unwrap_or((argument, ""));
+        let (user_id, allow) = match action {
+            "" => {
+                return match self.check_dm(msg.author_id).await {
+                    Ok(()) => String::from("You can chat with me in direct messages"),
+                    Err(reason) => reason,
+                }
+            }
+            "optin" | "optout" => {
+                if self.config.dm_mode != DmMode::OptIn {
+                    return String::from("Direct messages are not opt-in");
+                }
+                (msg.author_id, action == "optin")
+            }
+            "allow" | "deny" => {
+                if !self.config.is_admin(msg.author_id) {
+                    return String::from("Only admins can allow users");
+                }
+                match target
+                    .trim()
+                    .trim_start_matches("<@")
+                    .trim_end_matches('>')
+                    .parse()
+                {
+                    Ok(user_id) => (user_id, action == "allow"),
+                    Err(_) => return format!("Invalid user: {}", target),
+                }
+            }
+            _ => return String::from("Usage: !dm optin|optout, or !dm allow|deny <user id>"),
+        };
+
+        let mut local_settings = self.settings.lock().await;
+        local_settings.dm_users.retain(|id| *id != user_id);
+        if allow {
+            local_settings.dm_users.push(user_id);
+        }
+        local_settings.save();
+        match (allow, user_id == msg.author_id) {
+            (true, true) => String::from("You can now chat with me in direct messages"),
+            (false, true) => String::from("You will no longer get replies in direct messages"),
+            (true, false) => format!("<@{}> can now chat with me in direct messages", user_id),
+            (false, false) => format!(
+                "<@{}> can no longer chat with me in direct messages",
+                user_id
+            ),
+        }
+    }
+
+    // !summarize summarizes the channel's history without touching its conversation
+    pub async fn summarize_channel(
+        &self,
+        transport: &dyn Transport,
+        msg: &IncomingMessage,
+        argument: &str,
+    ) -> String {
+        let (limit, since) = match parse_range(argument, chrono::Utc::now().timestamp()) {
+            Ok(range) => range,
+            Err(error) => return error,
+        };
+        let messages = match transport.history(limit, since).await {
+            Ok(messages) => messages,
+            Err(error) => return error,
+        };
+        let lines = format_history(&messages);
+        info!("Summarizing {} messages...", lines.len());
+
+        let model = self.pick_model(msg.channel_id, false, 0).await;
+        let chunk_tokens = self
+            .config
+            .summary_chunk_tokens
+            .min(self.config.context_window(&model) / 2);
+        // about 4 characters per token
+        let max_chars = chunk_tokens.max(1) as usize * 4;
+        match summarize(&self.gemini, &model, &lines, max_chars).await {
+            Ok((summary, usage_metadata)) => {
+                self.record_usage(msg, &usage_metadata, &model).await;
+                format!("**Summary of {} messages**\n{}", lines.len(), summary)
+            }
+            Err(error) => error,
+        }
+    }
+
+    // "!translate <language> <text>", or "!translate <language>" in a reply to translate that message
+    pub async fn translate_command(&self, msg: &IncomingMessage, argument: &str) -> String {
+        let (language, text) = argument
+            .split_once(char::is_whitespace)
+            .unwrap_or((argument, ""));
+        let text = match (text.trim(), msg.referenced.first()) {
+            ("", Some(referenced)) => referenced.content.as_str(),
+            (text, _) => text,
+        };
+        if language.is_empty() || text.is_empty() {
+            return String::from(
+                "Usage: !translate <language> <text>, or reply to a message with !translate <language>",
+            );
+        }
+        self.translate_text(msg, language, text).await
+    }
+
+    // reacting to a message with a flag translates it into the flag's language,
+    // msg is the reacted message with the author set to whoever reacted
+    pub async fn handle_reaction(
+        &self,
+        transport: &dyn Transport,
+        msg: &IncomingMessage,
+        emoji: &str,
+    ) {
+        if msg.author_id == msg.bot_id || msg.content.trim().is_empty() {
+            return;
+        }
+        let Some(language) = flag_language(emoji) else {
+            return;
+        };
+        if self.check_message(msg).await.is_err() {
+            return;
+        }
+        info!(
+            "Translating message {} into {}...",
+            msg.message_id, language
+        );
+        transport.typing().await;
+        for part in split_string(&self.translate_text(msg, language, &msg.content).await) {
+            transport.reply(&part).await;
+        }
+    }
+
+    async fn translate_text(&self, msg: &IncomingMessage, language: &str, text: &str) -> String {
+        let model = self.pick_model(msg.channel_id, false, 0).await;
+        match translate(&self.gemini, &model, language, text).await {
+            Ok((translation, usage_metadata)) => {
+                self.record_usage(msg, &usage_metadata, &model).await;
+                translation
+            }
+            Err(error) => error.to_string(),
+        }
+    }
+
+    // "!moderation on|off" turns moderation of the channel on or off, "!moderation log" makes the
+    // channel the guild's mod log, without argument shows it
+    pub async fn configure_moderation(&self, msg: &IncomingMessage, argument: &str) -> String {
+        let Some(guild_id) = msg.guild_id else {
+            return String::from("Moderation only works in servers");
+        };
+        let mut local_settings = self.settings.lock().await;
+        let moderated = match argument {
+            "" => {
+                return if local_settings.channel(msg.channel_id).moderated {
+                    String::from("Moderation is on in this channel")
+                } else {
+                    String::from("Moderation is off in this channel")
+                };
+            }
+            "log" => {
+                local_settings.guild_mut(guild_id).mod_log = Some(msg.channel_id);
+                local_settings.save();
+                return String::from("Flagged messages of this server now go to this channel");
+            }
+            "on" => true,
+            "off" => false,
+            _ => return String::from("Usage: !moderation [on|off|log]"),
+        };
+        // the mod log is per guild so flagged messages never leave their server
+        if moderated && local_settings.guild(guild_id).mod_log.is_none() {
+            return String::from(
+                "Use !moderation log in the channel flagged messages should go to first",
+            );
+        }
+        local_settings.channel_mut(msg.channel_id).moderated = moderated;
+        local_settings.save();
+        if moderated {
+            String::from("Moderation is now on in this channel, flagged messages go to the mod log")
+        } else {
+            String::from("Moderation is now off in this channel")
+        }
+    }
+
+    // classifies messages of moderated channels and posts the flagged ones to the mod log,
+    // nothing is deleted
+    pub async fn moderate(&self, transport: &dyn Transport, msg: &IncomingMessage) {
+        let Some(guild_id) = msg.guild_id else {
+            return;
+        };
+        let mod_log = {
+            let local_settings = self.settings.lock().await;
+            if !local_settings.channel(msg.channel_id).moderated {
+                return;
+            }
+            local_settings.guild(guild_id).mod_log
+        };
+        let Some(mod_log) = mod_log else {
+            return;
+        };
+        if msg.author_id == msg.bot_id || msg.channel_id == mod_log || msg.content.trim().is_empty()
+        {
+            return;
+        }
+        let now = chrono::Utc::now().timestamp();
+        if !self
+            .moderation_limits
+            .lock()
+            .await
+            .allow(msg.channel_id, now)
+        {
+            info!(
+                "Skipping moderation of message {}, too many messages",
+                msg.message_id
+            );
+            return;
+        }
+
+        let model = self.pick_model(msg.channel_id, false, 0).await;
+        let verdict = match classify(&self.gemini, &model, &msg.content).await {
+            Ok((verdict, usage_metadata)) => {
+                // the author didn't ask for it, so it doesn't count as their usage
+                let moderation = IncomingMessage {
+                    author_id: MODERATION_USER,
+                    ..msg.clone()
+                };
+                self.record_usage(&moderation, &usage_metadata, &model)
+                    .await;
+                verdict
+            }
+            Err(error) => {
+                error!("Error classifying message {}: {}", msg.message_id, error);
+                return;
+            }
+        };
+        if !verdict.is_flagged(self.config.moderation_threshold) {
+            return;
+        }
+        info!("Flagged message {} as {}", msg.message_id, verdict.category);
+        let mut report = format!(
+            "**Flagged {}** ({:.0}%) from <@{}> in <#{}>: {}\n{}\n",
+            verdict.category,
+            verdict.confidence * 100.0,
+            msg.author_id,
+            msg.channel_id,
+            verdict.reason,
+            jump_link(guild_id, msg.channel_id, msg.message_id)
+        );
+        for line in msg.content.lines() {
+            report.push_str(&format!("> {}\n", line));
+        }
+        for part in split_string(&report) {
+            transport.send_to(mod_log, &part).await;
+        }
+    }
+
+    // "!memory on|off|list|add <fact>|forget <number>|clear|export", the user's own facts only
+    pub async fn configure_memory(&self, msg: &IncomingMessage, argument: &str) -> String {
+        let (action, rest) = argument
+            .split_once(char::is_whitespace)
+            .unwrap_or((argument, ""));
+        let rest = rest.trim();
+        let mut store = self.memory.lock().await;
+        let enabled = store.is_enabled(msg.author_id);
+        let reply = match action {
+            "" if enabled => format!(
+                "Memory is on, I remember {} facts about you. Use !memory list|add|forget|clear|export|off",
+                store.facts(msg.author_id).len()
+            ),
+            "" => String::from(
+                "Memory is off. With !memory on I remember facts you tell me in every channel",
+            ),
+            "on" => {
+                store.set_enabled(msg.author_id, true);
+                String::from("Memory is now on, tell me what to remember or use !memory add <fact>")
+            }
+            "off" => {
+                store.set_enabled(msg.author_id, false);
+                String::from("Memory is now off and everything I remembered about you is forgotten")
+            }
+            "list" if store.facts(msg.author_id).is_empty() => {
+                String::from("I don't remember anything about you")
+            }
+            "list" => store
+                .facts(msg.author_id)
+                .iter()
+                .enumerate()
+                .map(|(i, fact)| format!("{}. {}", i + 1, fact.text))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            "add" => match store.add(msg.author_id, rest, chrono::Utc::now().timestamp()) {
+                Ok(()) => format!("I will remember: {}", rest),
+                Err(error) => return error,
+            },
+            "forget" => match rest.parse().ok().and_then(|i| store.forget(msg.author_id, i)) {
+                Some(fact) => format!("Forgot: {}", fact.text),
+                None => return String::from("Usage: !memory forget <number from !memory list>"),
+            },
+            "clear" => {
+                store.clear(msg.author_id);
+                String::from("Forgot everything about you")
+            }
+            _ => {
+                return String::from(
+                    "Usage: !memory [on|off|list|add <fact>|forget <number>|clear|export]",
+                )
+            }
+        };
+        store.save();
+        reply
+    }
+
+    // the user's facts as a json file for "!memory export", only in direct messages since
+    // the file would be public in a server
+    pub async fn export_memory(&self, msg: &IncomingMessage) -> Result<(String, Vec<u8>), String> {
+        if msg.guild_id.is_some() {
+            return Err(String::from(
+                "Your facts are private, use !memory export in a direct message",
+            ));
+        }
+        let export = self.memory.lock().await.export(msg.author_id);
+        Ok((
+            format!("memory-{}.json", msg.author_id),
+            export.into_bytes(),
+        ))
+    }
+
+    // !reindex reads the docs directory again and replaces the index
+    pub async fn reindex_docs(&self) -> String {
+        let Some(dir) = &self.config.docs_dir else {
+            return String::from("Set DOCS_DIR to answer from documents");
+        };
+        info!("Indexing documents in {}...", dir);
+        match build_index(
+            &self.gemini,
+            &self.config.embedding_model,
+            std::path::Path::new(dir),
+        )
+        .await
+        {
+            Ok((chunks, files)) => {
+                let count = chunks.len();
+                self.docs.lock().await.replace(chunks);
+                format!("Indexed {} chunks from {} files", count, files)
+            }
+            Err(error) => {
+                error!("Error indexing documents: {}", error);
+                error
+            }
+        }
+    }
+
+    // the docs and the channel's knowledge base closest to the question as instructions,
+    // and the footers that list what was used
+    pub async fn retrieve(&self, channel_id: u64, question: &str) -> (Vec<String>, Vec<String>) {
+        let kb_channel = self.kb_channel(channel_id).await;
+        let has_docs = !self.docs.lock().await.chunks.is_empty();
+        let has_kb = self.knowledge.lock().await.channel(kb_channel).is_some();
+        if !(has_docs || has_kb) || question.trim().is_empty() {
+            return (Vec::new(), Vec::new());
+        }
+        let query = match self
+            .gemini
+            .embed(
+                &self.config.embedding_model,
+                &[question.to_string()],
+                "RETRIEVAL_QUERY",
+            )
+            .await
+        {
+            Ok(mut embeddings) => embeddings.remove(0),
+            // the question is still answered, without the material
+            Err(error) => {
+                error!("Error embedding question: {}", error);
+                return (Vec::new(), Vec::new());
+            }
+        };
+        let mut context = Vec::new();
+        let mut footers = Vec::new();
+
+        let docs = self.docs.lock().await;
+        let found = docs.search(&query, self.config.docs_top_k, self.config.docs_min_score);
+        if !found.is_empty() {
+            let mut instruction = String::from(
+                "These excerpts of the documentation may help answer the message. \
+                When you use one, cite it by its name in brackets, like [setup.md#Install]. \
+                If they don't answer it, say so instead of guessing.",
+            );
+            let mut sources: Vec<String> = Vec::new();
+            for chunk in found {
+                let citation = chunk.citation();
+                instruction.push_str(&format!("\n\n[{}]\n{}", citation, chunk.text));
+                if !sources.contains(&citation) {
+                    sources.push(citation);
+                }
+            }
+            context.push(instruction);
+            footers.push(format!("-# Sources: {}", sources.join(", ")));
+        }
+        drop(docs);
+
+        let knowledge = self.knowledge.lock().await;
+        let found = knowledge.search(
+            kb_channel,
+            &query,
+            self.config.docs_top_k,
+            self.config.docs_min_score,
+        );
+        if !found.is_empty() {
+            let mut instruction = String::from(
+                "The admins of this channel attached this material for questions asked here:",
+            );
+            let mut labels: Vec<String> = Vec::new();
+            for (entry, chunk) in found {
+                instruction.push_str(&format!("\n\n[{}]\n{}", entry.label(), chunk.text));
+                if !labels.contains(&entry.label()) {
+                    labels.push(entry.label());
+                }
+            }
+            context.push(instruction);
+            footers.push(format!("-# Knowledge base: {}", labels.join(", ")));
+        }
+        (context, footers)
+    }
+
+    // the bot's threads use the knowledge base of the channel they were started in
+    async fn kb_channel(&self, channel_id: u64) -> u64 {
+        self.settings
+            .lock()
+            .await
+            .threads
+            .get(&channel_id)
+            .map_or(channel_id, |thread| thread.parent_id)
+    }
+
+    // "!kb [list]" shows the channel's material, admins change it with
+    // "!kb add [title:] <text>" or files attached to it, "!kb pins", "!kb remove <number>" and "!kb clear"
+    pub async fn configure_kb(
+        &self,
+        transport: &dyn Transport,
+        msg: &IncomingMessage,
+        argument: &str,
+    ) -> String {
+        let (action, rest) = argument
+            .split_once(char::is_whitespace)
+            .unwrap_or((argument, ""));
+        let rest = rest.trim();
+        let channel_id = self.kb_channel(msg.channel_id).await;
+        if !matches!(action, "" | "list") && !self.config.is_admin(msg.author_id) {
+            return String::from("Only admins can change the knowledge base");
+        }
+        match action {
+            "" | "list" => {
+                let knowledge = self.knowledge.lock().await;
+                match knowledge
+                    .channel(channel_id)
+                    .filter(|kb| !kb.entries.is_empty())
+                {
+                    Some(kb) => {
+                        let mut list: Vec<String> = kb
+                            .entries
+                            .iter()
+                            .map(|entry| {
+                                format!("{} ({}, {} characters)", entry.label(), entry.kind, entry.chars)
+                            })
+                            .collect();
+                        list.push(format!(
+                            "{} / {} characters used",
+                            kb.total_chars(),
+                            self.config.kb_max_chars
+                        ));
+                        list.join("\n")
+                    }
+                    None => String::from("The knowledge base of this channel is empty"),
+                }
+            }
+            "add" if !msg.attachments.is_empty() => {
+                let mut replies = Vec::new();
+                for attachment in &msg.attachments {
+                    let reply = match self.read_file(transport, msg, attachment).await {
+                        Ok(text) => {
+                            self.add_kb_entry(msg, channel_id, &attachment.filename, "file", &text)
+                                .await
+                        }
+                        Err(error) => error,
+                    };
+                    replies.push(reply);
+                }
+                replies.join("\n")
+            }
+            "add" if !rest.is_empty() => {
+                let (title, text) = parse_note(rest);
+                self.add_kb_entry(msg, channel_id, &title, "note", &text)
+                    .await
+            }
+            "pins" => {
+                let pins = match transport.pins().await {
+                    Ok(pins) => pins,
+                    Err(error) => return error,
+                };
+                let text = pins
+                    .iter()
+                    .filter(|pin| !pin.content.trim().is_empty())
+                    .map(|pin| format!("{}: {}", pin.author_name, pin.content))
+                    .collect::<Vec<String>>()
+                    .join("\n\n");
+                if text.is_empty() {
+                    return String::from("This channel has no pinned messages");
+                }
+                self.add_kb_entry(msg, channel_id, "Pinned messages", "pin", &text)
+                    .await
+            }
+            "remove" => {
+                let mut knowledge = self.knowledge.lock().await;
+                match rest
+                    .trim_start_matches('#')
+                    .parse()
+                    .ok()
+                    .and_then(|id| knowledge.remove(channel_id, id))
+                {
+                    Some(entry) => {
+                        knowledge.save();
+                        format!("Removed {}", entry.label())
+                    }
+                    None => String::from("Usage: !kb remove <number from !kb list>"),
+                }
+            }
+            "clear" => {
+                let mut knowledge = self.knowledge.lock().await;
+                knowledge.clear(channel_id);
+                knowledge.save();
+                String::from("Cleared the knowledge base of this channel")
+            }
+            _ => String::from("Usage: !kb [list|add [title:] <text>|pins|remove <number>|clear], files can be attached to !kb add"),
+        }
+    }
+
+    // text of an uploaded text file, pdfs are transcribed by gemini
+    async fn read_file(
+        &self,
+        transport: &dyn Transport,
+        msg: &IncomingMessage,
+        attachment: &Attachment,
+    ) -> Result<String, String> {
+        let content_type = attachment.content_type.as_deref().unwrap_or("");
+        let is_pdf = content_type == "application/pdf";
+        if !is_pdf && !content_type.starts_with("text/") {
+            return Err(format!("Unsupported file type: {}", attachment.filename));
+        }
+        let content = transport.download(attachment).await.map_err(|error| {
+            error!("{}", error);
+            format!("Error downloading {}", attachment.filename)
+        })?;
+        if !is_pdf {
+            return Ok(String::from_utf8_lossy(&content).to_string());
+        }
+        info!("Transcribing {}...", attachment.filename);
+        let model = self.pick_model(msg.channel_id, true, 0).await;
+        let file = InlineData {
+            mimeType: content_type.to_string(),
+            data: base64::engine::general_purpose::STANDARD.encode(content),
+        };
+        let (text, usage_metadata) = self
+            .gemini
+            .one_shot_with_file(
+                &model,
+                "Transcribe the text of the document. Keep headings, lists and tables, \
+                leave out page numbers, headers and footers.",
+                "Transcribe this document.",
+                file,
+            )
+            .await
+            .map_err(|error| format!("Error reading {}: {}", attachment.filename, error))?;
+        self.record_usage(msg, &usage_metadata, &model).await;
+        Ok(text)
+    }
+
+    // embeds the material and adds it to the channel, pins replace the previous pins
+    async fn add_kb_entry(
+        &self,
+        msg: &IncomingMessage,
+        channel_id: u64,
+        title: &str,
+        kind: &str,
+        text: &str,
+    ) -> String {
+        let replaced = (kind == "pin").then_some(kind);
+        let mut entry: KbEntry = new_entry(
+            title,
+            kind,
+            text.trim(),
+            msg.author_id,
+            chrono::Utc::now().timestamp(),
+        );
+        if entry.chars == 0 {
+            return format!("{} has no text", title);
+        }
+        if let Err(error) = self.knowledge.lock().await.check_size(
+            channel_id,
+            entry.chars,
+            replaced,
+            self.config.kb_max_chars,
+        ) {
+            return error;
+        }
+        let texts: Vec<String> = entry
+            .chunks
+            .iter()
+            .map(|chunk| format!("{}\n{}", chunk.citation(), chunk.text))
+            .collect();
+        let embeddings = match self
+            .gemini
+            .embed(&self.config.embedding_model, &texts, "RETRIEVAL_DOCUMENT")
+            .await
+        {
+            Ok(embeddings) => embeddings,
+            Err(error) => return format!("Error adding {}: {}", title, error),
+        };
+        for (chunk, embedding) in entry.chunks.iter_mut().zip(embeddings) {
+            chunk.embedding = embedding;
+        }
+        let mut knowledge = self.knowledge.lock().await;
+        if let Some(kind) = replaced {
+            knowledge.remove_kind(channel_id, kind);
+        }
+        let chars = entry.chars;
+        let id = knowledge.add(channel_id, entry);
+        knowledge.save();
+        format!("Added #{} {} ({} characters)", id, title, chars)
+    }
+
+    // "!history [page]" pages through the turns gemini sees, admins can
+    // "!history delete <number>", "!history pin <number>" and "!history unpin <number>"
+    pub async fn conversation_history(&self, msg: &IncomingMessage, argument: &str) -> String {
+        let (action, rest) = argument
+            .split_once(char::is_whitespace)
+            .unwrap_or((argument, ""));
+        let conversation = self.get_conversation(msg.channel_id).await;
+        let mut local_conversation = conversation.lock().await;
+        let page = if argument.is_empty() {
+            Some(1)
+        } else {
+            argument.parse().ok()
+        };
+        if let Some(page) = page {
+            return history_page(&local_conversation.contents, page);
+        }
+        if !matches!(action, "delete" | "pin" | "unpin") {
+            return String::from("Usage: !history [page], !history delete|pin|unpin <number>");
+        }
+        if !self.config.is_admin(msg.author_id) {
+            return String::from("Only admins can change the history");
+        }
+        let index = match rest.trim().trim_start_matches('#').parse::<usize>() {
+            Ok(number) if number >= 1 && number <= local_conversation.contents.len() => number - 1,
+            _ => return format!("Usage: !history {} <number from !history>", action),
+        };
+        match action {
+            // the question and its reply go together so the turns keep alternating
+            "delete" => {
+                let removed = local_conversation.remove_exchange(index);
+                if removed.len() == 2 {
+                    format!("Deleted turns #{} and #{}", removed.start + 1, removed.end)
+                } else {
+                    format!("Deleted turn #{}", index + 1)
+                }
+            }
+            "pin" => match local_conversation.pin_turn(index) {
+                Ok(()) => format!(
+                    "Pinned turn #{}, it won't be dropped from the history",
+                    index + 1
+                ),
+                Err(error) => error,
+            },
+            _ => {
+                local_conversation.contents[index].pinned = false;
+                format!("Unpinned turn #{}", index + 1)
+            }
+        }
+    }
+
+    // the channel's conversation as a transcript file, "!export [md|json|html]"
+    pub async fn export_conversation(
+        &self,
+        channel_id: u64,
+        argument: &str,
+    ) -> Result<(String, Vec<u8>), String> {
+        let format =
+            Format::parse(argument).ok_or_else(|| String::from("Usage: !export [md|json|html]"))?;
+        let conversation = self.get_conversation(channel_id).await;
+        let local_conversation = conversation.lock().await;
+        if local_conversation.contents.is_empty() {
+            return Err(String::from("There is no conversation to export yet"));
+        }
+        let filename = format!("conversation-{}.{}", channel_id, format.extension());
+        let transcript = export(&local_conversation.contents, format);
+        Ok((filename, transcript.into_bytes()))
+    }
+
+    // replaces the channel's conversation with the json transcript attached to "!import"
+    pub async fn import_conversation(
+        &self,
+        transport: &dyn Transport,
+        msg: &IncomingMessage,
+    ) -> String {
+        let Some(attachment) = msg.attachments.first() else {
+            return String::from("Attach a JSON transcript from !export json to !import");
+        };
+        let content = match transport.download(attachment).await {
+            Ok(content) => content,
+            Err(error) => {
+                error!("{}", error);
+                return format!("Error downloading {}", attachment.filename);
+            }
+        };
+        let contents = match parse_transcript(&String::from_utf8_lossy(&content)) {
+            Ok(contents) => contents,
+            Err(error) => return error,
+        };
+        let turns = contents.len();
+        let conversation = self.get_conversation(msg.channel_id).await;
+        let mut local_conversation = conversation.lock().await;
+        local_conversation.contents = contents;
+        local_conversation.token_count = 0;
+        local_conversation.truncated = false;
+        // the buttons of earlier replies would point at the imported turns
+        local_conversation.replies += 1;
+        format!("Imported {} turns", turns)
+    }
+
+    // remembers a thread the bot started so it answers everything in it
+    pub async fn track_thread(&self, thread_id: u64, parent_id: u64) {
+        let mut local_settings = self.settings.lock().await;
+        local_settings.threads.insert(
+            thread_id,
+            BotThread {
+                parent_id,
+                last_activity: chrono::Utc::now().timestamp(),
+            },
+        );
+        local_settings.save();
+    }
+
+    // true if the channel is one of the bot's threads, which then counts as active
+    pub async fn touch_thread(&self, channel_id: u64) -> bool {
+        let now = chrono::Utc::now().timestamp();
+        self.settings.lock().await.touch_thread(channel_id, now)
+    }
+
+    // saves what is only kept in memory between sweeps
+    pub async fn flush(&self) {
+        self.settings.lock().await.flush();
+        self.usage.lock().await.flush();
+    }
+
+    // forgets the threads inactive for longer than the archive timeout and returns them to be archived
+    pub async fn expire_threads(&self, now: i64) -> Vec<u64> {
+        if self.config.thread_archive_minutes <= 0 {
+            return Vec::new();
+        }
+        let cutoff = now - self.config.thread_archive_minutes * 60;
+        let expired: Vec<u64> = {
+            let mut local_settings = self.settings.lock().await;
+            let expired: Vec<u64> = local_settings
+                .threads
+                .iter()
+                .filter(|(_, thread)| thread.last_activity <= cutoff)
+                .map(|(id, _)| *id)
+                .collect();
+            if !expired.is_empty() {
+                local_settings.threads.retain(|id, _| !expired.contains(id));
+                local_settings.save();
+            }
+            expired
+        };
+        let mut local_conversations = self.conversations.lock().await;
+        for id in &expired {
+            local_conversations.remove(id);
+        }
+        expired
+    }
+
+    pub async fn handle_message(&self, transport: &dyn Transport, msg: &IncomingMessage) {
+        if msg.author_id == msg.bot_id {
+            return;
+        }
+
+        let is_admin = self.config.is_admin(msg.author_id);
+
+        if is_admin && msg.content == "!resetgemini" {
+            info!("Reseting conversation...");
+            self.reset_conversation(msg.channel_id).await;
+            transport.typing().await;
+            transport.say("Conversation has been reset!").await;
+            return;
+        }
+
+        if let Some(name) = command(&msg.content, "!persona").filter(|_| is_admin) {
+            transport
+                .reply(&self.switch_persona(msg.channel_id, name).await)
+                .await;
+            return;
+        }
+
+        if let Some(period) = command(&msg.content, "!usage") {
+            for part in split_string(&self.usage_report(msg, period).await) {
+                transport.reply(&part).await;
+            }
+            return;
+        }
+
+        if command(&msg.content, "!models").is_some() {
+            transport.typing().await;
+            for part in split_string(&self.list_models().await) {
+                transport.reply(&part).await;
+            }
+            return;
+        }
+
+        if let Some(argument) = command(&msg.content, "!gen") {
+            transport
+                .reply(&self.configure_generation(msg, argument).await)
+                .await;
+            return;
+        }
+
+        if let Some(name) = command(&msg.content, "!model") {
+            if !name.is_empty() && !is_admin {
+                transport.reply("Only admins can change the model").await;
+                return;
+            }
+            transport
+                .reply(&self.set_model(msg.channel_id, name).await)
+                .await;
+            return;
+        }
+
+        if let Some(argument) = command(&msg.content, "!summarize") {
+            if let Err(reason) = self.check_message(msg).await {
+                transport.reply(&reason).await;
+                return;
+            }
+            transport.typing().await;
+            for part in split_string(&self.summarize_channel(transport, msg, argument).await) {
+                transport.reply(&part).await;
+            }
+            return;
+        }
+
+        if let Some(argument) = command(&msg.content, "!translate") {
+            if let Err(reason) = self.check_message(msg).await {
+                transport.reply(&reason).await;
+                return;
+            }
+            transport.typing().await;
+            for part in split_string(&self.translate_command(msg, argument).await) {
+                transport.reply(&part).await;
+            }
+            return;
+        }
+
+        if let Some(argument) = command(&msg.content, "!moderation").filter(|_| is_admin) {
+            transport
+                .reply(&self.configure_moderation(msg, argument).await)
+                .await;
+            return;
+        }
+
+        if command(&msg.content, "!memory") == Some("export") {
+            let result = match self.export_memory(msg).await {
+                Ok((filename, content)) => transport.send_file("", &filename, content).await,
+                Err(error) => Err(error),
+            };
+            if let Err(error) = result {
+                transport.reply(&error).await;
+            }
+            return;
+        }
+
+        if let Some(argument) = command(&msg.content, "!memory") {
+            for part in split_string(&self.configure_memory(msg, argument).await) {
+                transport.reply(&part).await;
+            }
+            return;
+        }
+
+        if is_admin && msg.content == "!reindex" {
+            transport.typing().await;
+            transport.reply(&self.reindex_docs().await).await;
+            return;
+        }
+
+        if let Some(argument) = command(&msg.content, "!kb") {
+            transport.typing().await;
+            for part in split_string(&self.configure_kb(transport, msg, argument).await) {
+                transport.reply(&part).await;
+            }
+            return;
+        }
+
+        if let Some(argument) = command(&msg.content, "!history") {
+            for part in split_string(&self.conversation_history(msg, argument).await) {
+                transport.reply(&part).await;
+            }
+            return;
+        }
+
+        if let Some(argument) = command(&msg.content, "!export") {
+            let result = match self.export_conversation(msg.channel_id, argument).await {
+                Ok((filename, content)) => transport.send_file("", &filename, content).await,
+                Err(error) => Err(error),
+            };
+            if let Err(error) = result {
+                transport.reply(&error).await;
+            }
+            return;
+        }
+
+        if command(&msg.content, "!import").is_some() {
+            if !is_admin {
+                transport
+                    .reply("Only admins can import conversations")
+                    .await;
+                return;
+            }
+            transport.typing().await;
+            transport
+                .reply(&self.import_conversation(transport, msg).await)
+                .await;
+            return;
+        }
+
+        if let Some(argument) = command(&msg.content, "!dm") {
+            transport
+                .reply(&self.configure_dm(msg, argument).await)
+                .await;
+            return;
+        }
+
+        // check if starts with question mark
+        let question_mark: bool = msg.content.starts_with("? ");
+        // the bot answers everything in its own threads and in direct messages
+        let in_thread = self.touch_thread(msg.channel_id).await;
+        let in_dm = msg.guild_id.is_none();
+
+        if msg.mentions_bot || question_mark || in_thread || in_dm {
+            if let Err(reason) = self.check_message(msg).await {
+                transport.reply(&reason).await;
+                return;
+            }
+
+            // takes out flags like "--temp 0.2"
+            let (no_mention_msg, overrides) = match parse_flags(&strip_trigger(msg)) {
+                Ok(parsed) => parsed,
+                Err(error) => {
+                    transport.reply(&error).await;
+                    return;
+                }
+            };
+
+            if !in_thread
+                && msg.guild_id.is_some()
+                && self.config.thread_channels.contains(&msg.channel_id)
+            {
+                match transport.create_thread(&thread_name(&no_mention_msg)).await {
+                    Ok((thread_id, thread)) => {
+                        info!("Started thread {}", thread_id);
+                        self.track_thread(thread_id, msg.channel_id).await;
+                        let thread_msg = IncomingMessage {
+                            channel_id: thread_id,
+                            ..msg.clone()
+                        };
+                        self.answer(
+                            thread.as_ref(),
+                            &thread_msg,
+                            no_mention_msg,
+                            &overrides,
+                            &[],
+                        )
+                        .await;
+                        return;
+                    }
+                    // answers in the channel instead
+                    Err(error) => error!("Error creating thread: {}", error),
+                }
+            }
+            self.answer(transport, msg, no_mention_msg, &overrides, &[])
+                .await;
+        }
+    }
+
+    // asks gemini and replies with the answer
+    async fn answer(
+        &self,
+        transport: &dyn Transport,
+        msg: &IncomingMessage,
+        no_mention_msg: String,
+        overrides: &GenerationConfig,
+        previous: &[u64],
+    ) {
+        // sends typing indicator thing to discord
+        transport.typing().await;
+
+        // images of the message and of the messages it replies to
+        let mut images: Vec<InlineData> = Vec::new();
+
+        // checks if there is attachment and grabs the first one
+        if let Some(attachment) = msg.attachments.first() {
+            info!("Attachment found: {:?}", attachment);
+            // gets the attachment content type
+            let content_type = match &attachment.content_type {
+                Some(value) => value.to_string(),
+                None => {
+                    // returns if for some reason there is no content type
+                    transport
+                        .reply("Could not find content type of attachment")
+                        .await;
+                    return;
+                }
+            };
+            // check if attachment is in supported format
+            if SUPPORTED_IMAGE_TYPES.contains(&content_type.as_str()) {
+                // download the attachment
+                info!("Received an image as attachment, downloading...");
+                let content = match transport.download(attachment).await {
+                    Ok(content) => content,
+                    Err(err) => {
+                        // if for some reason download fails
+                        error!("{}", err);
+                        transport.reply("Error downloading attachment").await;
+                        return;
+                    }
+                };
+                // converts to base64
+                info!("Converting to base64...");
+                let base64 = base64::engine::general_purpose::STANDARD.encode(content);
+                info!("Size is: {}", base64.len());
+                images.push(InlineData {
+                    mimeType: content_type,
+                    data: base64,
+                });
+            } else {
+                transport.reply("Unsupported attachment type").await;
+                return;
+            }
+        }
+
+        // quotes the messages being replied to
+        let quote = self.quote_references(transport, msg, &mut images).await;
+        let no_mention_msg = if quote.is_empty() {
+            no_mention_msg
+        } else {
+            format!("{}\n{}", quote, no_mention_msg)
+        };
+
+        let (context, footers) = self.retrieve(msg.channel_id, &no_mention_msg).await;
+        let response = self
+            .send_msg_to_gemini(msg, no_mention_msg, images, overrides, context)
+            .await;
+        let mut chunks = split_string(&response.0);
+        if response.1.totalTokenCount != -1 {
+            for footer in &footers {
+                add_footer(&mut chunks, footer);
+            }
+        }
+        // if answer was really successful
+        if response.1.totalTokenCount != -1 && self.config.context_footer {
+            let footer = format!(
+                "-# {} · context {} / {} tokens",
+                response.2,
+                format_tokens(response.1.totalTokenCount as i64),
+                format_tokens(self.config.context_window(&response.2) as i64)
+            );
+            add_footer(&mut chunks, &footer);
+        }
+        let buttons = if response.1.totalTokenCount != -1 {
+            self.buttons(msg).await
+        } else {
+            Vec::new()
+        };
+        // the previous reply is edited in place when the prompt was edited
+        let mut reply_ids = Vec::new();
+        for (i, part) in chunks.iter().enumerate() {
+            let part_buttons: &[Button] = if i + 1 == chunks.len() { &buttons } else { &[] };
+            let id = match previous.get(i) {
+                Some(id) => {
+                    transport.edit(*id, part, part_buttons).await;
+                    Some(*id)
+                }
+                None if part_buttons.is_empty() => transport.reply(part).await,
+                None => transport.reply_with_buttons(part, part_buttons).await,
+            };
+            reply_ids.extend(id);
+        }
+        for id in previous.iter().skip(chunks.len()) {
+            transport.delete(*id).await;
+        }
+        if response.1.totalTokenCount != -1 {
+            self.tag_reply(msg, reply_ids).await;
+            self.record_usage(msg, &response.1, &response.2).await;
+            if let Some(status) = self.presence_status().await {
+                transport.set_presence(&status).await;
+            }
+        }
+    }
+
+    // remembers the reply's messages next to the prompt they answer
+    async fn tag_reply(&self, msg: &IncomingMessage, reply_ids: Vec<u64>) {
+        if msg.message_id == 0 {
+            return;
+        }
+        let conversation = self.get_conversation(msg.channel_id).await;
+        let mut local_conversation = conversation.lock().await;
+        if let Some(index) = local_conversation.find_message(msg.message_id) {
+            if let Some(reply) = local_conversation.contents.get_mut(index + 1) {
+                reply.message_ids = reply_ids;
+            }
+        }
+    }
+
+    // whether the message is a prompt or reply in the channel's conversation,
+    // lets transports skip fetching edits of every other message
+    pub async fn tracks_message(&self, channel_id: u64, message_id: u64) -> bool {
+        let Some(conversation) = self.conversations.lock().await.get(&channel_id).cloned() else {
+            return false;
+        };
+        let found = conversation.lock().await.find_message(message_id).is_some();
+        found
+    }
+
+    // an edited prompt is answered again if it is the latest one, the reply is edited in place
+    pub async fn handle_edit(&self, transport: &dyn Transport, msg: &IncomingMessage) {
+        if msg.author_id == msg.bot_id {
+            return;
+        }
+        let Some(conversation) = self
+            .conversations
+            .lock()
+            .await
+            .get(&msg.channel_id)
+            .cloned()
+        else {
+            return;
+        };
+        let Ok((text, overrides)) = parse_flags(&strip_trigger(msg)) else {
+            return;
+        };
+
+        let mut local_conversation = conversation.lock().await;
+        let Some(index) = local_conversation.find_message(msg.message_id) else {
+            return;
+        };
+        if index + 2 != local_conversation.contents.len() {
+            info!("Ignoring edit of an older prompt");
+            return;
+        }
+        // discord also sends updates when embeds load
+        if local_conversation.contents[index].text().ends_with(&text) {
+            return;
+        }
+        if let Err(reason) = self.check_message(msg).await {
+            transport.reply(&reason).await;
+            return;
+        }
+        let previous = local_conversation.contents[index + 1].message_ids.clone();
+        local_conversation.pop_exchange();
+        drop(local_conversation);
+
+        info!("Prompt was edited, answering again...");
+        self.answer(transport, msg, text, &overrides, &previous)
+            .await;
+    }
+
+    // a deleted prompt or reply takes its exchange out of the history
+    pub async fn handle_delete(&self, channel_id: u64, message_id: u64) {
+        let Some(conversation) = self.conversations.lock().await.get(&channel_id).cloned() else {
+            return;
+        };
+        let mut local_conversation = conversation.lock().await;
+        if let Some(index) = local_conversation.find_message(message_id) {
+            info!("Message was deleted, removing its exchange...");
+            local_conversation.remove_exchange(index);
+        }
+    }
+
+    // buttons for the channel's latest reply, continue only shows up if it was cut off
+    async fn buttons(&self, msg: &IncomingMessage) -> Vec<Button> {
+        let conversation = self.get_conversation(msg.channel_id).await;
+        let local_conversation = conversation.lock().await;
+        let mut actions = vec![ButtonAction::Regenerate];
+        if local_conversation.truncated {
+            actions.push(ButtonAction::Continue);
+        }
+        actions.push(ButtonAction::Delete);
+        actions
+            .into_iter()
+            .map(|action| Button {
+                action,
+                reply: local_conversation.replies,
+                user_id: msg.author_id,
+            })
+            .collect()
+    }
+
+    // msg is who pressed the button and where, only the latest reply changes the history
+    pub async fn handle_button(
+        &self,
+        transport: &dyn Transport,
+        msg: &IncomingMessage,
+        custom_id: &str,
+    ) {
+        let Some(button) = Button::parse(custom_id) else {
+            error!("Unknown button: {}", custom_id);
+            return;
+        };
+        if msg.author_id != button.user_id && !self.config.is_admin(msg.author_id) {
+            transport
+                .reply("Only the person who asked can use these buttons")
+                .await;
+            return;
+        }
+        // regenerating and continuing ask gemini again, deleting is always allowed
+        if button.action != ButtonAction::Delete {
+            if let Err(reason) = self.check_message(msg).await {
+                transport.reply(&reason).await;
+                return;
+            }
+        }
+
+        let conversation = self.get_conversation(msg.channel_id).await;
+        let mut local_conversation = conversation.lock().await;
+        let latest = local_conversation.replies == button.reply;
+        match button.action {
+            ButtonAction::Delete => {
+                // every chunk of a long reply is deleted, not only the one with the buttons
+                let mut reply_ids = Vec::new();
+                if latest {
+                    info!("Deleting last exchange...");
+                    let ids = local_conversation
+                        .contents
+                        .last()
+                        .map(|reply| reply.message_ids.clone())
+                        .unwrap_or_default();
+                    if local_conversation.pop_exchange().is_some() {
+                        reply_ids = ids;
+                    }
+                }
+                drop(local_conversation);
+                if reply_ids.is_empty() {
+                    transport.delete_message().await;
+                }
+                for id in reply_ids {
+                    transport.delete(id).await;
+                }
+            }
+            _ if !latest => {
+                transport
+                    .reply("Only the latest reply can be regenerated or continued")
+                    .await;
+            }
+            ButtonAction::Regenerate => {
+                let Some(question) = local_conversation.pop_exchange() else {
+                    transport.reply("Nothing to regenerate").await;
+                    return;
+                };
+                drop(local_conversation);
+                info!("Regenerating last reply...");
+                // keeps the prompt's message so it can still be edited or deleted
+                let msg = IncomingMessage {
+                    message_id: question.message_ids.first().copied().unwrap_or(0),
+                    ..msg.clone()
+                };
+                let default = GenerationConfig::default();
+                self.answer(transport, &msg, question.text(), &default, &[])
+                    .await;
+            }
+            ButtonAction::Continue => {
+                drop(local_conversation);
+                info!("Continuing last reply...");
+                self.answer(
+                    transport,
+                    msg,
+                    String::from("Continue exactly where you left off."),
+                    &GenerationConfig::default(),
+                    &[],
+                )
+                .await;
+            }
+        }
+    }
+
+    // formats the reply chain oldest first and downloads its images, the bot's replies are
+    // skipped if they are still in the history, summaries or dropped replies are quoted
+    pub async fn quote_references(
+        &self,
+        transport: &dyn Transport,
+        msg: &IncomingMessage,
+        images: &mut Vec<InlineData>,
+    ) -> String {
+        let depth = self.config.reply_chain_depth;
+        let mut chain = msg.referenced.clone();
+        if chain.len() == 1 && depth > 1 {
+            let fetched = transport.reply_chain(depth).await;
+            if !fetched.is_empty() {
+                chain = fetched;
+            }
+        }
+        let conversation = self.get_conversation(msg.channel_id).await;
+        let local_conversation = conversation.lock().await;
+        chain.retain(|referenced| {
+            referenced.author_id != msg.bot_id
+                || local_conversation
+                    .find_message(referenced.message_id)
+                    .is_none()
+        });
+        drop(local_conversation);
+        let mut quote = String::new();
+        for referenced in chain.iter().rev() {
+            quote.push_str(&format!("{} wrote:\n", referenced.author_name));
+            for line in referenced.content.lines() {
+                quote.push_str(&format!("> {}\n", line));
+            }
+
+            for attachment in &referenced.attachments {
+                let content_type = attachment.content_type.as_deref().unwrap_or("");
+                if !SUPPORTED_IMAGE_TYPES.contains(&content_type) {
+                    quote.push_str(&format!("> [attachment: {}]\n", attachment.filename));
+                    continue;
+                }
+                info!("Downloading image of replied message...");
+                match transport.download(attachment).await {
+                    Ok(content) => images.push(InlineData {
+                        mimeType: content_type.to_string(),
+                        data: base64::engine::general_purpose::STANDARD.encode(content),
+                    }),
+                    Err(err) => error!("Error downloading attachment of replied message: {}", err),
+                }
+            }
+        }
+        quote
+    }
+
+    // empty name lists the personas, otherwise switches the channel to the persona
+    pub async fn switch_persona(&self, channel_id: u64, name: &str) -> String {
+        let conversation = self.get_conversation(channel_id).await;
+        let mut local_conversation = conversation.lock().await;
+        if name.is_empty() {
+            format!(
+                "Current persona: {}\nAvailable: {}",
+                local_conversation.persona,
+                self.personas.names().join(", ")
+            )
+        } else if let Some(persona) = self.personas.get(name) {
+            local_conversation.set_persona(&persona.name, &persona.system_instruction);
+            format!("Switched to persona {}", persona.name)
+        } else {
+            format!("Unknown persona: {}", name)
+        }
+    }
+
+    pub async fn record_usage(
+        &self,
+        msg: &IncomingMessage,
+        usage_metadata: &UsageMetadata,
+        model: &str,
+    ) {
+        let key = UsageKey {
+            user_id: msg.author_id,
+            channel_id: msg.channel_id,
+            guild_id: msg.guild_id.unwrap_or(0),
+        };
+        let date = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        let mut local_usage = self.usage.lock().await;
+        // counts gemini didn't send are -1, they would lower the totals
+        local_usage.record(
+            &date,
+            key,
+            model,
+            usage_metadata.promptTokenCount.max(0) as i64,
+            usage_metadata.candidatesTokenCount.max(0) as i64,
+        );
+    }
+
+    // what the bot's status should be, None if it shouldn't be touched
+    pub async fn presence_status(&self) -> Option<String> {
+        match &self.config.presence {
+            PresenceMode::Off => None,
+            PresenceMode::Static(status) => Some(status.clone()),
+            PresenceMode::DailyUsage => {
+                let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+                let report = self.usage.lock().await.report(&today, |_| true);
+                Some(format!(
+                    "Today: {} tokens",
+                    format_tokens(report.total.total_tokens())
+                ))
+            }
+        }
+    }
+
+    // !usage shows today, "!usage month" this month, or a given "YYYY-MM-DD" / "YYYY-MM"
+    pub async fn usage_report(&self, msg: &IncomingMessage, argument: &str) -> String {
+        let now = chrono::Utc::now();
+        let period = match argument {
+            "" | "day" | "daily" | "today" => now.format("%Y-%m-%d").to_string(),
+            "month" | "monthly" => now.format("%Y-%m").to_string(),
+            other => other.to_string(),
+        };
+
+        // reports are scoped to the guild, or to the user in direct messages
+        let user_id = msg.author_id;
+        let report = self
+            .usage
+            .lock()
+            .await
+            .report(&period, |record| match msg.guild_id {
+                Some(guild_id) => record.guild_id == guild_id,
+                None => record.guild_id == 0 && record.user_id == user_id,
+            });
+        report.format(&self.config.model_prices)
+    }
+}
+
+// the message without the mention or the "? " that triggered the bot
+pub fn strip_trigger(msg: &IncomingMessage) -> String {
+    let text = msg.content.replace(&format!("<@{}>", msg.bot_id), "");
+    if let Some(text) = text.strip_prefix(' ') {
+        text.to_string()
+    } else if let Some(text) = text.strip_prefix("? ") {
+        text.to_string()
+    } else {
+        text
+    }
+}
+
+// the first line of the question, cut to fit discord's 100 character limit
+pub fn thread_name(question: &str) -> String {
+    let line = question.lines().map(str::trim).find(|l| !l.is_empty());
+    match line {
+        Some(line) if line.chars().count() > 90 => {
+            format!("{}...", line.chars().take(90).collect::<String>())
+        }
+        Some(line) => line.to_string(),
+        None => String::from("Conversation"),
+    }
+}
+
+// one page of !history, the role, size and start of each turn
+pub fn history_page(contents: &[Contents], page: usize) -> String {
+    if contents.is_empty() {
+        return String::from("The conversation is empty");
+    }
+    let pages = contents.len().div_ceil(HISTORY_PAGE_SIZE);
+    let page = page.clamp(1, pages);
+    let start = (page - 1) * HISTORY_PAGE_SIZE;
+    let mut lines: Vec<String> = contents
+        .iter()
+        .enumerate()
+        .skip(start)
+        .take(HISTORY_PAGE_SIZE)
+        .map(|(index, content)| {
+            let text = content
+                .text()
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ");
+            let mut preview: String = text.chars().take(HISTORY_PREVIEW_CHARS).collect();
+            if preview.len() < text.len() {
+                preview = format!("{}...", preview.trim_end());
+            }
+            if preview.is_empty() {
+                preview = String::from("(no text)");
+            }
+            format!(
+                "**#{}** {} · ~{} tokens{}\n> {}",
+                index + 1,
+                content.role,
+                content.estimated_tokens(),
+                if content.pinned { " · pinned" } else { "" },
+                preview
+            )
+        })
+        .collect();
+    let total: usize = contents
+        .iter()
+        .map(|content| content.estimated_tokens())
+        .sum();
+    lines.push(format!(
+        "-# Page {} / {} · {} turns · ~{} tokens",
+        page,
+        pages,
+        contents.len(),
+        total
+    ));
+    lines.join("\n")
+}
+
+// appends the footer to the last chunk, or as a new chunk if it wouldn't fit
+pub fn add_footer(chunks: &mut Vec<String>, footer: &str) {
+    match chunks.last_mut() {
+        Some(last) if last.len() + footer.len() < 2000 => {
+            last.push('\n');
+            last.push_str(footer);
+        }
+        _ => chunks.push(footer.to_string()),
+    }
+}
+
+// 950 -> "950", 12345 -> "12.3k", 1000000 -> "1M"
+pub fn format_tokens(tokens: i64) -> String {
+    let (value, suffix) = if tokens >= 1_000_000 {
+        (tokens as f64 / 1_000_000.0, "M")
+    } else if tokens >= 1_000 {
+        (tokens as f64 / 1_000.0, "k")
+    } else {
+        return tokens.to_string();
+    };
+    let text = format!("{:.1}", value);
+    format!("{}{}", text.trim_end_matches(".0"), suffix)
+}
+
+pub fn split_string(s: &str) -> Vec<String> {
+    let max_len = 2000;
+    if s.is_empty() || max_len == 0 {
+        return vec![];
+    }
+
+    let mut parts = Vec::new();
+
+    let mut start = 0;
+    while start < s.len() {
+        let mut end = usize::min(start + max_len, s.len());
+        // don't cut multi byte characters in half
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        parts.push(s[start..end].to_string());
+        start = end;
+    }
+    parts
+}
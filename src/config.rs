@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+
+use tracing::{error, info};
+
+// price of a model in USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+}
+
+// what the bot shows as its discord status
+#[derive(Debug, Clone, PartialEq)]
+pub enum PresenceMode {
+    Off,
+    Static(String),
+    DailyUsage,
+}
+
+// who can talk to the bot in direct messages, admins always can
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DmMode {
+    Open,
+    // only users an admin allowed with !dm allow
+    Allowlist,
+    // users allow themselves with !dm optin
+    OptIn,
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub model: String,
+    pub base_url: String,
+    pub usage_file: String,
+    pub personas_file: String,
+    pub model_prices: HashMap<String, ModelPrice>,
+    pub presence: PresenceMode,
+    pub context_footer: bool,
+    // overrides the context window of every model
+    pub context_window: Option<i32>,
+    pub settings_file: String,
+    // facts users asked the bot to remember
+    pub memory_file: String,
+    // users allowed to use admin commands
+    pub admin_ids: Vec<u64>,
+    // models picked automatically when a channel has no model set
+    pub route_multimodal_model: Option<String>,
+    pub route_long_context_model: Option<String>,
+    pub route_long_context_tokens: i32,
+    // how many messages of a reply chain are quoted, 0 turns it off
+    pub reply_chain_depth: usize,
+    // channels where asking the bot starts a thread
+    pub thread_channels: Vec<u64>,
+    // threads the bot started are archived after this long without messages, 0 keeps them open
+    pub thread_archive_minutes: i64,
+    pub dm_mode: DmMode,
+    // tokens a user can use per day in direct messages, 0 is unlimited
+    pub dm_daily_tokens: i64,
+    // rounds of tool calls gemini can make for one answer
+    pub max_tool_steps: usize,
+    // tools gemini is never offered
+    pub disabled_tools: Vec<String>,
+    // most tokens of history sent in one summary request, also limited by the context window
+    pub summary_chunk_tokens: i32,
+    // how many times a failed gemini request is sent again
+    pub gemini_retries: u32,
+    // how sure the classification must be to flag a message, from 0 to 1
+    pub moderation_threshold: f64,
+    // messages classified per channel and minute, the rest aren't checked
+    pub moderation_per_minute: usize,
+    // markdown and text files answers can use, !reindex reads them again
+    pub docs_dir: Option<String>,
+    pub docs_index_file: String,
+    pub embedding_model: String,
+    // how many chunks of the docs are added to a question, and how similar they must be
+    pub docs_top_k: usize,
+    pub docs_min_score: f32,
+    // reference material admins attached to channels with !kb
+    pub kb_file: String,
+    // most characters of material one channel can have
+    pub kb_max_chars: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            model: String::from("gemini-1.5-flash-001"),
+            base_url: String::from("https://generativelanguage.googleapis.com/v1beta"),
+            usage_file: String::from("usage.json"),
+            personas_file: String::from("personas.json"),
+            model_prices: HashMap::new(),
+            presence: PresenceMode::DailyUsage,
+            context_footer: true,
+            context_window: None,
+            settings_file: String::from("settings.json"),
+            memory_file: String::from("memory.json"),
+            admin_ids: vec![crate::bot::OWNER_ID],
+            route_multimodal_model: None,
+            route_long_context_model: None,
+            route_long_context_tokens: 100_000,
+            reply_chain_depth: 3,
+            thread_channels: Vec::new(),
+            thread_archive_minutes: 60,
+            dm_mode: DmMode::Open,
+            dm_daily_tokens: 0,
+            max_tool_steps: 5,
+            disabled_tools: Vec::new(),
+            summary_chunk_tokens: 30_000,
+            gemini_retries: 2,
+            moderation_threshold: 0.8,
+            moderation_per_minute: 20,
+            docs_dir: None,
+            docs_index_file: String::from("docs_index.json"),
+            embedding_model: String::from("text-embedding-004"),
+            docs_top_k: 4,
+            docs_min_score: 0.5,
+            kb_file: String::from("knowledge.json"),
+            kb_max_chars: 100_000,
+        }
+    }
+}
+
+impl Config {
+    // every setting can be overridden with an environment variable
+    pub fn from_env() -> Self {
+        let defaults = Config::default();
+        let model = env_or("GEMINI_MODEL", &defaults.model);
+        let base_url = env_or("GEMINI_BASE_URL", &defaults.base_url);
+        let usage_file = env_or("USAGE_FILE", &defaults.usage_file);
+        let personas_file = env_or("PERSONAS_FILE", &defaults.personas_file);
+        let model_prices = match std::env::var("MODEL_PRICES") {
+            Ok(value) => parse_prices(&value),
+            Err(_) => defaults.model_prices,
+        };
+        info!("Loaded prices for {} models", model_prices.len());
+        let presence = match std::env::var("PRESENCE") {
+            Ok(value) => parse_presence(&value),
+            Err(_) => defaults.presence,
+        };
+        let context_footer = env_parse("CONTEXT_FOOTER", defaults.context_footer);
+        let context_window = env_optional("CONTEXT_WINDOW").and_then(|value| {
+            value
+                .parse()
+                .map_err(|_| error!("Invalid CONTEXT_WINDOW: {}", value))
+                .ok()
+        });
+        let settings_file = env_or("SETTINGS_FILE", &defaults.settings_file);
+        let memory_file = env_or("MEMORY_FILE", &defaults.memory_file);
+        let admin_ids = match std::env::var("ADMIN_IDS") {
+            Ok(value) => parse_ids(&value),
+            Err(_) => defaults.admin_ids,
+        };
+        let route_multimodal_model = env_optional("ROUTE_MULTIMODAL_MODEL");
+        let route_long_context_model = env_optional("ROUTE_LONG_CONTEXT_MODEL");
+        let route_long_context_tokens = env_parse(
+            "ROUTE_LONG_CONTEXT_TOKENS",
+            defaults.route_long_context_tokens,
+        );
+        let reply_chain_depth = env_parse("REPLY_CHAIN_DEPTH", defaults.reply_chain_depth);
+        let thread_channels = match std::env::var("THREAD_CHANNELS") {
+            Ok(value) => parse_ids(&value),
+            Err(_) => defaults.thread_channels,
+        };
+        let thread_archive_minutes =
+            env_parse("THREAD_ARCHIVE_MINUTES", defaults.thread_archive_minutes);
+        let dm_mode = match std::env::var("DM_MODE") {
+            Ok(value) => parse_dm_mode(&value).unwrap_or_else(|| {
+                error!("Invalid DM_MODE: {}", value);
+                defaults.dm_mode
+            }),
+            Err(_) => defaults.dm_mode,
+        };
+        let dm_daily_tokens = env_parse("DM_DAILY_TOKENS", defaults.dm_daily_tokens);
+        let max_tool_steps = env_parse("MAX_TOOL_STEPS", defaults.max_tool_steps);
+        let disabled_tools = match std::env::var("DISABLED_TOOLS") {
+            Ok(value) => value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect(),
+            Err(_) => defaults.disabled_tools,
+        };
+
+        let summary_chunk_tokens = env_parse("SUMMARY_CHUNK_TOKENS", defaults.summary_chunk_tokens);
+        let gemini_retries = env_parse("GEMINI_RETRIES", defaults.gemini_retries);
+        let moderation_threshold = env_parse("MODERATION_THRESHOLD", defaults.moderation_threshold);
+        let moderation_per_minute =
+            env_parse("MODERATION_PER_MINUTE", defaults.moderation_per_minute);
+        let docs_dir = env_optional("DOCS_DIR");
+        let docs_index_file = env_or("DOCS_INDEX_FILE", &defaults.docs_index_file);
+        let embedding_model = env_or("EMBEDDING_MODEL", &defaults.embedding_model);
+        let docs_top_k = env_parse("DOCS_TOP_K", defaults.docs_top_k);
+        let docs_min_score = env_parse("DOCS_MIN_SCORE", defaults.docs_min_score);
+        let kb_file = env_or("KB_FILE", &defaults.kb_file);
+        let kb_max_chars = env_parse("KB_MAX_CHARS", defaults.kb_max_chars);
+
+        Config {
+            model,
+            base_url,
+            usage_file,
+            personas_file,
+            model_prices,
+            presence,
+            context_footer,
+            context_window,
+            settings_file,
+            memory_file,
+            admin_ids,
+            route_multimodal_model,
+            route_long_context_model,
+            route_long_context_tokens,
+            reply_chain_depth,
+            thread_channels,
+            thread_archive_minutes,
+            dm_mode,
+            dm_daily_tokens,
+            max_tool_steps,
+            disabled_tools,
+            summary_chunk_tokens,
+            gemini_retries,
+            moderation_threshold,
+            moderation_per_minute,
+            docs_dir,
+            docs_index_file,
+            embedding_model,
+            docs_top_k,
+            docs_min_score,
+            kb_file,
+            kb_max_chars,
+        }
+    }
+
+    pub fn is_admin(&self, user_id: u64) -> bool {
+        self.admin_ids.contains(&user_id)
+    }
+
+    pub fn context_window(&self, model: &str) -> i32 {
+        self.context_window.unwrap_or_else(|| context_window(model))
+    }
+}
+
+fn env_or(key: &str, default: &str) -> String {
+    std::env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+// None if the variable is missing or empty
+fn env_optional(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+// falls back to the default if the variable is missing or invalid
+fn env_parse<T: std::str::FromStr>(key: &str, default: T) -> T {
+    match std::env::var(key) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            error!("Invalid {}: {}", key, value);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+// parses a comma separated list of discord ids
+pub fn parse_ids(value: &str) -> Vec<u64> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .filter_map(|id| match id.parse() {
+            Ok(id) => Some(id),
+            Err(_) => {
+                error!("Invalid id: {}", id);
+                None
+            }
+        })
+        .collect()
+}
+
+// "off", "usage" for today's token total, anything else is shown as is
+pub fn parse_presence(value: &str) -> PresenceMode {
+    match value {
+        "off" => PresenceMode::Off,
+        "usage" => PresenceMode::DailyUsage,
+        status => PresenceMode::Static(status.to_string()),
+    }
+}
+
+// "open", "allowlist" or "optin"
+pub fn parse_dm_mode(value: &str) -> Option<DmMode> {
+    match value.trim() {
+        "open" => Some(DmMode::Open),
+        "allowlist" => Some(DmMode::Allowlist),
+        "optin" | "opt-in" => Some(DmMode::OptIn),
+        _ => None,
+    }
+}
+
+// input token limit of the known models
+pub fn context_window(model: &str) -> i32 {
+    if model.starts_with("gemini-1.5-pro") {
+        2_097_152
+    } else if model.starts_with("gemini-1.5-flash") {
+        1_048_576
+    } else {
+        32_768
+    }
+}
+
+// parses "model=input/output;model2=input/output", prices are per million tokens
+pub fn parse_prices(value: &str) -> HashMap<String, ModelPrice> {
+    let mut prices = HashMap::new();
+    for entry in value.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let Some((model, price)) = entry.split_once('=') else {
+            error!("Invalid MODEL_PRICES entry: {}", entry);
+            continue;
+        };
+        let Some((input, output)) = price.split_once('/') else {
+            error!("Invalid MODEL_PRICES entry: {}", entry);
+            continue;
+        };
+        match (input.trim().parse::<f64>(), output.trim().parse::<f64>()) {
+            (Ok(input), Ok(output)) => {
+                prices.insert(model.trim().to_string(), ModelPrice { input, output });
+            }
+            _ => error!("Invalid MODEL_PRICES entry: {}", entry),
+        }
+    }
+    prices
+}
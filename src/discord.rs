@@ -0,0 +1,674 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serenity::all::{
+    ActivityData, ButtonStyle, ChannelId, ComponentInteraction, CreateActionRow,
+    CreateAllowedMentions, CreateAttachment, CreateButton, CreateMessage, CreateThread,
+    EditMessage, EditThread, GuildId, Interaction, MessageId, MessagePagination,
+    MessageUpdateEvent, Reaction, ReactionType,
+};
+use serenity::async_trait;
+use serenity::model::channel::Message;
+use serenity::model::gateway::Ready;
+use serenity::prelude::*;
+use tracing::{error, info};
+
+use crate::bot::{
+    Attachment, Bot, Button, ButtonAction, IncomingMessage, QuotedMessage, Transport,
+};
+use crate::discord_tools::DiscordHttp;
+use crate::summarize::HistoryMessage;
+use crate::translate::flag_language;
+
+pub struct Handler {
+    pub bot: Arc<Bot>,
+    // handed to the bot's discord tools once the client is ready
+    http: DiscordHttp,
+    // the thread archive sweep is started on the first ready event only
+    sweeping: AtomicBool,
+}
+
+impl Handler {
+    pub fn new(bot: Arc<Bot>, http: DiscordHttp) -> Self {
+        Handler {
+            bot,
+            http,
+            sweeping: AtomicBool::new(false),
+        }
+    }
+}
+
+// replies to a discord message
+pub struct DiscordTransport<'a> {
+    pub ctx: &'a Context,
+    pub msg: &'a Message,
+}
+
+#[async_trait]
+impl Transport for DiscordTransport<'_> {
+    async fn reply(&self, text: &str) -> Option<u64> {
+        match self.msg.reply(&self.ctx.http, text).await {
+            Ok(sent) => Some(sent.id.get()),
+            Err(why) => {
+                error!("Error sending message: {why:?}");
+                None
+            }
+        }
+    }
+
+    async fn say(&self, text: &str) {
+        if let Err(why) = self.msg.channel_id.say(&self.ctx.http, text).await {
+            error!("Error sending message: {why:?}");
+        }
+    }
+
+    async fn typing(&self) {
+        if let Err(why) = self.msg.channel_id.broadcast_typing(&self.ctx.http).await {
+            error!("Error sending typing: {why:?}");
+        }
+    }
+
+    async fn set_presence(&self, status: &str) {
+        self.ctx.set_presence(
+            Option::from(ActivityData::custom(status)),
+            Default::default(),
+        );
+    }
+
+    async fn download(&self, attachment: &Attachment) -> Result<Vec<u8>, String> {
+        download(attachment).await
+    }
+
+    async fn create_thread(&self, name: &str) -> Result<(u64, Box<dyn Transport>), String> {
+        let thread = self
+            .msg
+            .channel_id
+            .create_thread_from_message(&self.ctx.http, self.msg.id, CreateThread::new(name))
+            .await
+            .map_err(|why| format!("{why:?}"))?;
+        let transport = ThreadTransport {
+            ctx: self.ctx.clone(),
+            channel_id: thread.id,
+        };
+        Ok((thread.id.get(), Box::new(transport)))
+    }
+
+    async fn reply_with_buttons(&self, text: &str, buttons: &[Button]) -> Option<u64> {
+        let message = reply_message(text, self.msg).components(components(buttons));
+        send(self.ctx, self.msg.channel_id, message).await
+    }
+
+    async fn edit(&self, message_id: u64, text: &str, buttons: &[Button]) {
+        edit(self.ctx, self.msg.channel_id, message_id, text, buttons).await;
+    }
+
+    async fn delete(&self, message_id: u64) {
+        delete(self.ctx, self.msg.channel_id, message_id).await;
+    }
+
+    async fn send_to(&self, channel_id: u64, text: &str) {
+        let message = CreateMessage::new()
+            .content(text)
+            .allowed_mentions(CreateAllowedMentions::new());
+        send(self.ctx, ChannelId::new(channel_id), message).await;
+    }
+
+    async fn history(
+        &self,
+        limit: usize,
+        since: Option<i64>,
+    ) -> Result<Vec<HistoryMessage>, String> {
+        let mut messages: Vec<HistoryMessage> = Vec::new();
+        // pages backwards from the command, discord returns at most 100 messages at a time
+        let mut before = self.msg.id;
+        while messages.len() < limit {
+            let page = self
+                .ctx
+                .http
+                .get_messages(
+                    self.msg.channel_id,
+                    Some(MessagePagination::Before(before)),
+                    Some((limit - messages.len()).min(100) as u8),
+                )
+                .await
+                .map_err(|why| {
+                    error!("Error fetching history: {why:?}");
+                    String::from("Error fetching the channel's messages")
+                })?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            before = last.id;
+            let mut reached_since = false;
+            for message in page {
+                if since.is_some_and(|since| message.timestamp.unix_timestamp() < since) {
+                    reached_since = true;
+                    break;
+                }
+                messages.push(HistoryMessage {
+                    author_name: message
+                        .author
+                        .global_name
+                        .clone()
+                        .unwrap_or_else(|| message.author.name.clone()),
+                    content: message.content,
+                    timestamp: message.timestamp.unix_timestamp(),
+                });
+            }
+            if reached_since {
+                break;
+            }
+        }
+        messages.reverse();
+        Ok(messages)
+    }
+
+    async fn send_file(&self, text: &str, filename: &str, content: Vec<u8>) -> Result<(), String> {
+        let message = CreateMessage::new()
+            .content(text)
+            .add_file(CreateAttachment::bytes(content, filename))
+            .reference_message(self.msg);
+        send(self.ctx, self.msg.channel_id, message)
+            .await
+            .map(|_| ())
+            .ok_or_else(|| format!("Error sending {}", filename))
+    }
+
+    async fn reply_chain(&self, depth: usize) -> Vec<QuotedMessage> {
+        reply_chain(self.ctx, self.msg, depth).await
+    }
+
+    async fn pins(&self) -> Result<Vec<HistoryMessage>, String> {
+        let pins = self
+            .ctx
+            .http
+            .get_pins(self.msg.channel_id)
+            .await
+            .map_err(|why| {
+                error!("Error fetching pins: {why:?}");
+                String::from("Error fetching the channel's pinned messages")
+            })?;
+        // discord lists the newest pin first
+        Ok(pins
+            .into_iter()
+            .rev()
+            .map(|message| HistoryMessage {
+                author_name: message
+                    .author
+                    .global_name
+                    .clone()
+                    .unwrap_or_else(|| message.author.name.clone()),
+                content: message.content,
+                timestamp: message.timestamp.unix_timestamp(),
+            })
+            .collect())
+    }
+}
+
+// posts in a thread the bot started, replies are plain messages in it
+pub struct ThreadTransport {
+    pub ctx: Context,
+    pub channel_id: ChannelId,
+}
+
+#[async_trait]
+impl Transport for ThreadTransport {
+    async fn reply(&self, text: &str) -> Option<u64> {
+        send(
+            &self.ctx,
+            self.channel_id,
+            CreateMessage::new().content(text),
+        )
+        .await
+    }
+
+    async fn say(&self, text: &str) {
+        self.reply(text).await;
+    }
+
+    async fn typing(&self) {
+        if let Err(why) = self.channel_id.broadcast_typing(&self.ctx.http).await {
+            error!("Error sending typing: {why:?}");
+        }
+    }
+
+    async fn set_presence(&self, status: &str) {
+        self.ctx.set_presence(
+            Option::from(ActivityData::custom(status)),
+            Default::default(),
+        );
+    }
+
+    async fn download(&self, attachment: &Attachment) -> Result<Vec<u8>, String> {
+        download(attachment).await
+    }
+
+    async fn reply_with_buttons(&self, text: &str, buttons: &[Button]) -> Option<u64> {
+        let message = CreateMessage::new()
+            .content(text)
+            .components(components(buttons));
+        send(&self.ctx, self.channel_id, message).await
+    }
+
+    async fn send_file(&self, text: &str, filename: &str, content: Vec<u8>) -> Result<(), String> {
+        let message = CreateMessage::new()
+            .content(text)
+            .add_file(CreateAttachment::bytes(content, filename));
+        send(&self.ctx, self.channel_id, message)
+            .await
+            .map(|_| ())
+            .ok_or_else(|| format!("Error sending {}", filename))
+    }
+
+    async fn edit(&self, message_id: u64, text: &str, buttons: &[Button]) {
+        edit(&self.ctx, self.channel_id, message_id, text, buttons).await;
+    }
+
+    async fn delete(&self, message_id: u64) {
+        delete(&self.ctx, self.channel_id, message_id).await;
+    }
+}
+
+// answers a button press on one of the bot's replies, in the reply's channel
+pub struct ComponentTransport {
+    pub ctx: Context,
+    pub interaction: ComponentInteraction,
+}
+
+#[async_trait]
+impl Transport for ComponentTransport {
+    async fn reply(&self, text: &str) -> Option<u64> {
+        let message = reply_message(text, &self.interaction.message);
+        send(&self.ctx, self.interaction.channel_id, message).await
+    }
+
+    async fn say(&self, text: &str) {
+        if let Err(why) = self.interaction.channel_id.say(&self.ctx.http, text).await {
+            error!("Error sending message: {why:?}");
+        }
+    }
+
+    async fn typing(&self) {
+        if let Err(why) = self
+            .interaction
+            .channel_id
+            .broadcast_typing(&self.ctx.http)
+            .await
+        {
+            error!("Error sending typing: {why:?}");
+        }
+    }
+
+    async fn set_presence(&self, status: &str) {
+        self.ctx.set_presence(
+            Option::from(ActivityData::custom(status)),
+            Default::default(),
+        );
+    }
+
+    async fn download(&self, attachment: &Attachment) -> Result<Vec<u8>, String> {
+        download(attachment).await
+    }
+
+    async fn reply_with_buttons(&self, text: &str, buttons: &[Button]) -> Option<u64> {
+        let message = CreateMessage::new()
+            .content(text)
+            .components(components(buttons));
+        send(&self.ctx, self.interaction.channel_id, message).await
+    }
+
+    async fn delete_message(&self) {
+        if let Err(why) = self.interaction.message.delete(&self.ctx.http).await {
+            error!("Error deleting message: {why:?}");
+        }
+    }
+
+    async fn edit(&self, message_id: u64, text: &str, buttons: &[Button]) {
+        edit(
+            &self.ctx,
+            self.interaction.channel_id,
+            message_id,
+            text,
+            buttons,
+        )
+        .await;
+    }
+
+    async fn delete(&self, message_id: u64) {
+        delete(&self.ctx, self.interaction.channel_id, message_id).await;
+    }
+}
+
+// a reply that doesn't ping the author, like Message::reply
+fn reply_message(text: &str, reference: &Message) -> CreateMessage {
+    let allowed_mentions = CreateAllowedMentions::new()
+        .replied_user(false)
+        .everyone(true)
+        .all_users(true)
+        .all_roles(true);
+    CreateMessage::new()
+        .content(text)
+        .reference_message(reference)
+        .allowed_mentions(allowed_mentions)
+}
+
+// returns the id of the sent message
+async fn send(ctx: &Context, channel_id: ChannelId, message: CreateMessage) -> Option<u64> {
+    match channel_id.send_message(&ctx.http, message).await {
+        Ok(sent) => Some(sent.id.get()),
+        Err(why) => {
+            error!("Error sending message: {why:?}");
+            None
+        }
+    }
+}
+
+async fn edit(
+    ctx: &Context,
+    channel_id: ChannelId,
+    message_id: u64,
+    text: &str,
+    buttons: &[Button],
+) {
+    let message = EditMessage::new()
+        .content(text)
+        .components(components(buttons));
+    if let Err(why) = channel_id
+        .edit_message(&ctx.http, MessageId::new(message_id), message)
+        .await
+    {
+        error!("Error editing message: {why:?}");
+    }
+}
+
+async fn delete(ctx: &Context, channel_id: ChannelId, message_id: u64) {
+    if let Err(why) = channel_id
+        .delete_message(&ctx.http, MessageId::new(message_id))
+        .await
+    {
+        error!("Error deleting message: {why:?}");
+    }
+}
+
+fn components(buttons: &[Button]) -> Vec<CreateActionRow> {
+    let buttons = buttons
+        .iter()
+        .map(|button| {
+            let style = match button.action {
+                ButtonAction::Delete => ButtonStyle::Danger,
+                _ => ButtonStyle::Secondary,
+            };
+            CreateButton::new(button.custom_id())
+                .label(button.label())
+                .style(style)
+        })
+        .collect::<Vec<_>>();
+    // an empty list removes the buttons of an edited message
+    if buttons.is_empty() {
+        return Vec::new();
+    }
+    vec![CreateActionRow::Buttons(buttons)]
+}
+
+async fn download(attachment: &Attachment) -> Result<Vec<u8>, String> {
+    let response = reqwest::get(&attachment.url)
+        .await
+        .map_err(|err| format!("{:?}", err.without_url()))?;
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|err| format!("{:?}", err.without_url()))?;
+    Ok(bytes.to_vec())
+}
+
+// archives the bot's threads once they have been inactive for too long,
+// and saves what changed since the last sweep
+async fn sweep_threads(ctx: Context, bot: Arc<Bot>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        for thread_id in bot.expire_threads(chrono::Utc::now().timestamp()).await {
+            info!("Archiving inactive thread {}", thread_id);
+            let archive = EditThread::new().archived(true);
+            if let Err(why) = ChannelId::new(thread_id)
+                .edit_thread(&ctx.http, archive)
+                .await
+            {
+                error!("Error archiving thread: {why:?}");
+            }
+        }
+        bot.flush().await;
+    }
+}
+
+fn to_attachments(msg: &Message) -> Vec<Attachment> {
+    msg.attachments
+        .iter()
+        .map(|a| Attachment {
+            url: a.url.clone(),
+            filename: a.filename.clone(),
+            content_type: a.content_type.clone(),
+        })
+        .collect()
+}
+
+fn quoted(message: &Message) -> QuotedMessage {
+    QuotedMessage {
+        message_id: message.id.get(),
+        author_id: message.author.id.get(),
+        author_name: message
+            .author
+            .global_name
+            .clone()
+            .unwrap_or_else(|| message.author.name.clone()),
+        content: message.content.clone(),
+        attachments: to_attachments(message),
+    }
+}
+
+// follows the replies up to depth messages, discord only includes the first one
+pub async fn reply_chain(ctx: &Context, msg: &Message, depth: usize) -> Vec<QuotedMessage> {
+    let mut chain = Vec::new();
+    let mut current = msg.referenced_message.as_deref().cloned();
+    while let Some(referenced) = current {
+        chain.push(quoted(&referenced));
+        if chain.len() >= depth {
+            break;
+        }
+        current = match (
+            &referenced.referenced_message,
+            &referenced.message_reference,
+        ) {
+            (Some(next), _) => Some(next.as_ref().clone()),
+            (None, Some(reference)) => match reference.message_id {
+                Some(message_id) => {
+                    info!("Fetching replied message {}...", message_id);
+                    match ctx.http.get_message(reference.channel_id, message_id).await {
+                        Ok(next) => Some(next),
+                        Err(why) => {
+                            error!("Error fetching replied message: {why:?}");
+                            None
+                        }
+                    }
+                }
+                None => None,
+            },
+            (None, None) => None,
+        };
+    }
+    chain
+}
+
+pub fn to_incoming(ctx: &Context, msg: &Message, reply_chain_depth: usize) -> IncomingMessage {
+    let bot_id = ctx.cache.current_user().id;
+    IncomingMessage {
+        message_id: msg.id.get(),
+        author_id: msg.author.id.get(),
+        channel_id: msg.channel_id.get(),
+        guild_id: msg.guild_id.map(|id| id.get()),
+        content: msg.content.clone(),
+        bot_id: bot_id.get(),
+        // checks if mentioned
+        mentions_bot: msg.mentions.iter().any(|mention| mention.id == bot_id),
+        attachments: to_attachments(msg),
+        // the rest of the chain costs requests, it is fetched once the bot answers
+        referenced: if reply_chain_depth > 0 {
+            msg.referenced_message
+                .as_deref()
+                .map(quoted)
+                .into_iter()
+                .collect()
+        } else {
+            Vec::new()
+        },
+        local: false,
+    }
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn message(&self, ctx: Context, msg: Message) {
+        // the bot never answers itself
+        if msg.author.id == ctx.cache.current_user().id {
+            return;
+        }
+        let incoming = to_incoming(&ctx, &msg, self.bot.config.reply_chain_depth);
+        let transport = DiscordTransport {
+            ctx: &ctx,
+            msg: &msg,
+        };
+        // moderation doesn't hold up the answer
+        tokio::join!(
+            self.bot.moderate(&transport, &incoming),
+            self.bot.handle_message(&transport, &incoming)
+        );
+    }
+
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _old_if_available: Option<Message>,
+        new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        // most edits are of messages the bot never answered, like embeds loading
+        if !self
+            .bot
+            .tracks_message(event.channel_id.get(), event.id.get())
+            .await
+        {
+            return;
+        }
+        // the new message is only there if it was cached
+        let msg = match new {
+            Some(msg) => msg,
+            None => match ctx.http.get_message(event.channel_id, event.id).await {
+                Ok(msg) => msg,
+                Err(why) => {
+                    error!("Error fetching edited message: {why:?}");
+                    return;
+                }
+            },
+        };
+        if msg.author.id == ctx.cache.current_user().id {
+            return;
+        }
+        let incoming = to_incoming(&ctx, &msg, self.bot.config.reply_chain_depth);
+        let transport = DiscordTransport {
+            ctx: &ctx,
+            msg: &msg,
+        };
+        self.bot.handle_edit(&transport, &incoming).await;
+    }
+
+    async fn message_delete(
+        &self,
+        _ctx: Context,
+        channel_id: ChannelId,
+        deleted_message_id: MessageId,
+        _guild_id: Option<GuildId>,
+    ) {
+        self.bot
+            .handle_delete(channel_id.get(), deleted_message_id.get())
+            .await;
+    }
+
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        let ReactionType::Unicode(emoji) = &reaction.emoji else {
+            return;
+        };
+        let Some(user_id) = reaction.user_id else {
+            return;
+        };
+        if user_id == ctx.cache.current_user().id {
+            return;
+        }
+        // only flags translate, the message isn't fetched for other reactions
+        if flag_language(emoji).is_none() {
+            return;
+        }
+        let msg = match reaction.message(&ctx.http).await {
+            Ok(msg) => msg,
+            Err(why) => {
+                error!("Error fetching reacted message: {why:?}");
+                return;
+            }
+        };
+        // the translation is asked for by whoever reacted
+        let incoming = IncomingMessage {
+            author_id: user_id.get(),
+            guild_id: reaction.guild_id.map(|id| id.get()),
+            ..to_incoming(&ctx, &msg, 0)
+        };
+        let transport = DiscordTransport {
+            ctx: &ctx,
+            msg: &msg,
+        };
+        self.bot.handle_reaction(&transport, &incoming, emoji).await;
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let Interaction::Component(component) = interaction else {
+            return;
+        };
+        if !component.data.custom_id.starts_with("gemini:") {
+            return;
+        }
+        // discord needs an answer within 3 seconds, the reply is sent as new messages
+        if let Err(why) = component.defer(&ctx.http).await {
+            error!("Error acknowledging button: {why:?}");
+        }
+        let incoming = IncomingMessage {
+            author_id: component.user.id.get(),
+            channel_id: component.channel_id.get(),
+            guild_id: component.guild_id.map(|id| id.get()),
+            bot_id: ctx.cache.current_user().id.get(),
+            ..Default::default()
+        };
+        let custom_id = component.data.custom_id.clone();
+        let transport = ComponentTransport {
+            ctx,
+            interaction: component,
+        };
+        self.bot
+            .handle_button(&transport, &incoming, &custom_id)
+            .await;
+    }
+
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        self.http.set(ctx.http.clone());
+        if let Some(status) = self.bot.presence_status().await {
+            ctx.set_presence(
+                Option::from(ActivityData::custom(status)),
+                Default::default(),
+            );
+        }
+        if !self.sweeping.swap(true, Ordering::SeqCst) {
+            tokio::spawn(sweep_threads(ctx.clone(), self.bot.clone()));
+        }
+        let msg = format!("{} is connected!", ready.user.name);
+        info!(msg);
+        println!("{}", msg);
+    }
+}
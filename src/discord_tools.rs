@@ -0,0 +1,295 @@
+use std::sync::{Arc, OnceLock};
+
+use serde_json::{json, Value};
+use serenity::all::{
+    ChannelId, GuildChannel, GuildId, Http, Member, Message, PartialGuild, Permissions, UserId,
+};
+use serenity::async_trait;
+
+use crate::tools::{Tool, ToolContext, ToolRegistry};
+
+// the client's http, set when discord is ready so the tools share its rate limits
+#[derive(Clone, Default)]
+pub struct DiscordHttp(Arc<OnceLock<Arc<Http>>>);
+
+impl DiscordHttp {
+    pub fn set(&self, http: Arc<Http>) {
+        let _ = self.0.set(http);
+    }
+
+    fn get(&self) -> Result<&Http, String> {
+        self.0
+            .get()
+            .map(|http| http.as_ref())
+            .ok_or_else(|| String::from("Not connected to Discord yet"))
+    }
+}
+
+// read only tools that look at the channel the question was asked in
+pub fn register(registry: &mut ToolRegistry, http: DiscordHttp) {
+    registry.register(ChannelHistory { http: http.clone() });
+    registry.register(MemberInfo { http: http.clone() });
+    registry.register(PinnedMessages { http: http.clone() });
+    registry.register(ChannelTopic { http });
+}
+
+async fn guild_channel(http: &Http, channel_id: u64) -> Result<GuildChannel, String> {
+    http.get_channel(ChannelId::new(channel_id))
+        .await
+        .map_err(|why| format!("Could not get the channel: {why}"))?
+        .guild()
+        .ok_or_else(|| String::from("Not a guild channel"))
+}
+
+// what decides whether a user can see a guild channel
+pub struct ChannelAccess {
+    pub guild: PartialGuild,
+    pub member: Member,
+    pub channel: GuildChannel,
+    // the channel a thread was started in
+    pub parent: Option<GuildChannel>,
+}
+
+// the user must be able to see what the tool shows, None is a direct message, which is their own
+pub fn check_access(access: Option<&ChannelAccess>, needed: Permissions) -> Result<(), String> {
+    let Some(access) = access else {
+        return Ok(());
+    };
+    // threads use the permissions of their channel
+    let channel = match (&access.channel.thread_metadata, &access.parent) {
+        (Some(_), Some(parent)) => parent,
+        _ => &access.channel,
+    };
+    if access
+        .guild
+        .user_permissions_in(channel, &access.member)
+        .contains(needed)
+    {
+        Ok(())
+    } else {
+        Err(String::from("The user is not allowed to see this"))
+    }
+}
+
+async fn fetch_access(http: &Http, ctx: &ToolContext) -> Result<Option<ChannelAccess>, String> {
+    let Some(guild_id) = ctx.guild_id else {
+        return Ok(None);
+    };
+    let guild_id = GuildId::new(guild_id);
+    let guild = http
+        .get_guild(guild_id)
+        .await
+        .map_err(|why| format!("Could not get the server: {why}"))?;
+    let member = http
+        .get_member(guild_id, UserId::new(ctx.user_id))
+        .await
+        .map_err(|why| format!("Could not get the member: {why}"))?;
+    let channel = guild_channel(http, ctx.channel_id).await?;
+    let parent = match (&channel.thread_metadata, channel.parent_id) {
+        (Some(_), Some(parent_id)) => Some(guild_channel(http, parent_id.get()).await?),
+        _ => None,
+    };
+    Ok(Some(ChannelAccess {
+        guild,
+        member,
+        channel,
+        parent,
+    }))
+}
+
+async fn check_permissions(
+    http: &Http,
+    ctx: &ToolContext,
+    needed: Permissions,
+) -> Result<(), String> {
+    check_access(fetch_access(http, ctx).await?.as_ref(), needed)
+}
+
+fn describe_message(msg: &Message) -> Value {
+    json!({
+        "author": msg.author.global_name.clone().unwrap_or_else(|| msg.author.name.clone()),
+        "content": msg.content,
+        "timestamp": msg.timestamp.to_string(),
+        "attachments": msg.attachments.iter().map(|a| a.filename.clone()).collect::<Vec<_>>(),
+    })
+}
+
+pub struct ChannelHistory {
+    http: DiscordHttp,
+}
+
+#[async_trait]
+impl Tool for ChannelHistory {
+    fn name(&self) -> &str {
+        "channel_history"
+    }
+
+    fn description(&self) -> &str {
+        "Returns the latest messages of the current channel, oldest first"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "limit": {
+                    "type": "integer",
+                    "description": "How many messages to return, from 1 to 100, defaults to 50"
+                },
+                "since_minutes": {
+                    "type": "integer",
+                    "description": "Only return messages from the last this many minutes"
+                }
+            }
+        })
+    }
+
+    async fn call(&self, ctx: &ToolContext, args: &Value) -> Result<Value, String> {
+        let http = self.http.get()?;
+        check_permissions(
+            http,
+            ctx,
+            Permissions::VIEW_CHANNEL | Permissions::READ_MESSAGE_HISTORY,
+        )
+        .await?;
+        let limit = args["limit"].as_u64().unwrap_or(50).clamp(1, 100) as u8;
+        let messages = http
+            .get_messages(ChannelId::new(ctx.channel_id), None, Some(limit))
+            .await
+            .map_err(|why| format!("Could not get the messages: {why}"))?;
+        let since = args["since_minutes"]
+            .as_i64()
+            .map(|minutes| chrono::Utc::now().timestamp() - minutes * 60);
+        let messages: Vec<Value> = messages
+            .iter()
+            .rev()
+            .filter(|msg| since.is_none_or(|since| msg.timestamp.unix_timestamp() >= since))
+            .map(describe_message)
+            .collect();
+        Ok(json!(messages))
+    }
+}
+
+pub struct MemberInfo {
+    http: DiscordHttp,
+}
+
+#[async_trait]
+impl Tool for MemberInfo {
+    fn name(&self) -> &str {
+        "member_info"
+    }
+
+    fn description(&self) -> &str {
+        "Returns the roles and join date of a member of the current server"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "user_id": {
+                    "type": "string",
+                    "description": "Discord id of the user, mentions look like <@id>"
+                }
+            },
+            "required": ["user_id"]
+        })
+    }
+
+    async fn call(&self, ctx: &ToolContext, args: &Value) -> Result<Value, String> {
+        let guild_id = GuildId::new(
+            ctx.guild_id
+                .ok_or_else(|| String::from("Only available in servers"))?,
+        );
+        let user_id: u64 = args["user_id"]
+            .as_str()
+            .unwrap_or("")
+            .trim_start_matches("<@")
+            .trim_end_matches('>')
+            .parse()
+            .map_err(|_| String::from("Invalid user id"))?;
+        let http = self.http.get()?;
+        let guild = http
+            .get_guild(guild_id)
+            .await
+            .map_err(|why| format!("Could not get the server: {why}"))?;
+        let member = http
+            .get_member(guild_id, UserId::new(user_id))
+            .await
+            .map_err(|why| format!("Could not get the member: {why}"))?;
+        let roles: Vec<String> = member
+            .roles
+            .iter()
+            .filter_map(|id| guild.roles.get(id).map(|role| role.name.clone()))
+            .collect();
+        Ok(json!({
+            "name": member.display_name(),
+            "roles": roles,
+            "joined_at": member.joined_at.map(|joined| joined.to_string()),
+        }))
+    }
+}
+
+pub struct PinnedMessages {
+    http: DiscordHttp,
+}
+
+#[async_trait]
+impl Tool for PinnedMessages {
+    fn name(&self) -> &str {
+        "pinned_messages"
+    }
+
+    fn description(&self) -> &str {
+        "Returns the pinned messages of the current channel"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({ "type": "object", "properties": {} })
+    }
+
+    async fn call(&self, ctx: &ToolContext, _args: &Value) -> Result<Value, String> {
+        let http = self.http.get()?;
+        check_permissions(
+            http,
+            ctx,
+            Permissions::VIEW_CHANNEL | Permissions::READ_MESSAGE_HISTORY,
+        )
+        .await?;
+        let pins = http
+            .get_pins(ChannelId::new(ctx.channel_id))
+            .await
+            .map_err(|why| format!("Could not get the pins: {why}"))?;
+        Ok(json!(pins.iter().map(describe_message).collect::<Vec<_>>()))
+    }
+}
+
+pub struct ChannelTopic {
+    http: DiscordHttp,
+}
+
+#[async_trait]
+impl Tool for ChannelTopic {
+    fn name(&self) -> &str {
+        "channel_topic"
+    }
+
+    fn description(&self) -> &str {
+        "Returns the name and topic of the current channel"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({ "type": "object", "properties": {} })
+    }
+
+    async fn call(&self, ctx: &ToolContext, _args: &Value) -> Result<Value, String> {
+        let http = self.http.get()?;
+        check_permissions(http, ctx, Permissions::VIEW_CHANNEL).await?;
+        let channel = guild_channel(http, ctx.channel_id).await?;
+        Ok(json!({
+            "name": channel.name,
+            "topic": channel.topic,
+        }))
+    }
+}
@@ -0,0 +1,206 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::gemini::Gemini;
+use crate::persist::{load_json, save_json};
+
+// most characters in one chunk, longer paragraphs are cut
+pub const CHUNK_CHARS: usize = 1500;
+const DOC_EXTENSIONS: [&str; 3] = ["md", "markdown", "txt"];
+
+// a piece of a document with its embedding
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Chunk {
+    // path of the file, relative to the docs directory
+    pub source: String,
+    // the markdown heading the chunk is under, empty before the first one
+    pub heading: String,
+    pub text: String,
+    pub embedding: Vec<f32>,
+}
+
+impl Chunk {
+    // how answers cite the chunk, "setup.md#Install"
+    pub fn citation(&self) -> String {
+        if self.heading.is_empty() {
+            self.source.clone()
+        } else {
+            format!("{}#{}", self.source, self.heading)
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct DocIndex {
+    pub chunks: Vec<Chunk>,
+    #[serde(skip)]
+    path: String,
+}
+
+impl DocIndex {
+    pub fn load(path: &str) -> Self {
+        let mut index: DocIndex = load_json(path, "docs index");
+        index.path = path.to_string();
+        index
+    }
+
+    pub fn save(&self) {
+        save_json(&self.path, self, "docs index", false);
+    }
+
+    // replaces the chunks, keeping where the index is saved
+    pub fn replace(&mut self, chunks: Vec<Chunk>) {
+        self.chunks = chunks;
+        self.save();
+    }
+
+    // the top_k chunks most similar to the query with at least min_score, best first
+    pub fn search(&self, query: &[f32], top_k: usize, min_score: f32) -> Vec<&Chunk> {
+        let mut scored: Vec<(f32, &Chunk)> = self
+            .chunks
+            .iter()
+            .map(|chunk| (cosine_similarity(query, &chunk.embedding), chunk))
+            .filter(|(score, _)| *score >= min_score)
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored
+            .into_iter()
+            .take(top_k)
+            .map(|(_, chunk)| chunk)
+            .collect()
+    }
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+// splits a document into chunks of whole paragraphs under the same heading
+pub fn chunk_document(source: &str, text: &str, max_chars: usize) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let mut heading = String::new();
+    let mut current = String::new();
+    let push = |chunks: &mut Vec<Chunk>, heading: &str, current: &mut String| {
+        if !current.trim().is_empty() {
+            chunks.push(Chunk {
+                source: source.to_string(),
+                heading: heading.to_string(),
+                text: current.trim().to_string(),
+                embedding: Vec::new(),
+            });
+        }
+        current.clear();
+    };
+    for paragraph in text.split("\n\n") {
+        let paragraph = paragraph.trim();
+        if paragraph.is_empty() {
+            continue;
+        }
+        // a heading starts a new chunk
+        if let Some(title) = paragraph
+            .lines()
+            .next()
+            .filter(|line| line.starts_with('#'))
+        {
+            push(&mut chunks, &heading, &mut current);
+            heading = title.trim_start_matches('#').trim().to_string();
+        }
+        if !current.is_empty() && current.len() + paragraph.len() + 2 > max_chars {
+            push(&mut chunks, &heading, &mut current);
+        }
+        // paragraphs longer than a chunk are cut at char boundaries
+        let mut rest = paragraph;
+        while rest.len() > max_chars {
+            let mut end = max_chars;
+            while !rest.is_char_boundary(end) {
+                end -= 1;
+            }
+            current.push_str(&rest[..end]);
+            push(&mut chunks, &heading, &mut current);
+            rest = &rest[end..];
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(rest);
+    }
+    push(&mut chunks, &heading, &mut current);
+    chunks
+}
+
+// the markdown and text files of the directory and its subdirectories, sorted by path
+pub fn read_documents(dir: &Path) -> Result<Vec<(String, String)>, String> {
+    let mut documents = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let entries = std::fs::read_dir(&current)
+            .map_err(|error| format!("Could not read {}: {}", current.display(), error))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let is_doc = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| DOC_EXTENSIONS.contains(&extension));
+            if !is_doc {
+                continue;
+            }
+            match std::fs::read_to_string(&path) {
+                Ok(text) => {
+                    let source = path
+                        .strip_prefix(dir)
+                        .unwrap_or(&path)
+                        .to_string_lossy()
+                        .replace('\\', "/");
+                    documents.push((source, text));
+                }
+                Err(error) => error!("Error reading {}: {}", path.display(), error),
+            }
+        }
+    }
+    documents.sort();
+    Ok(documents)
+}
+
+// reads, chunks and embeds every document of the directory, returns the chunks and how many files
+pub async fn build_index(
+    gemini: &Gemini,
+    model: &str,
+    dir: &Path,
+) -> Result<(Vec<Chunk>, usize), String> {
+    let documents = read_documents(dir)?;
+    let mut chunks: Vec<Chunk> = documents
+        .iter()
+        .flat_map(|(source, text)| chunk_document(source, text, CHUNK_CHARS))
+        .collect();
+    // the citation tells the model where a chunk comes from
+    let texts: Vec<String> = chunks
+        .iter()
+        .map(|chunk| format!("{}\n{}", chunk.citation(), chunk.text))
+        .collect();
+    let embeddings = gemini
+        .embed(model, &texts, "RETRIEVAL_DOCUMENT")
+        .await
+        .map_err(|error| error.to_string())?;
+    for (chunk, embedding) in chunks.iter_mut().zip(embeddings) {
+        chunk.embedding = embedding;
+    }
+    Ok((chunks, documents.len()))
+}
@@ -0,0 +1,582 @@
+use crate::structs::*;
+use crate::tools::{ToolContext, ToolRegistry};
+
+use std::fmt;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use tracing::{error, info, warn};
+
+// image formats gemini accepts as inline data
+pub const SUPPORTED_IMAGE_TYPES: [&str; 3] = ["image/jpg", "image/jpeg", "image/png"];
+
+// what went wrong with a request, displayed as the text shown to the user
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeminiError {
+    Serialize,
+    Request,
+    Body,
+    Deserialize,
+    Safety,
+    // the error message gemini sent, with the api key hidden
+    Api(String),
+    // the answer still didn't match the json schema after asking again
+    InvalidJson(String),
+}
+
+impl fmt::Display for GeminiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeminiError::Serialize => write!(f, "Error creating json of user's message"),
+            GeminiError::Request => write!(f, "Error sending POST request to gemini"),
+            GeminiError::Body => {
+                write!(
+                    f,
+                    "Error getting text from gemini's POST request's response"
+                )
+            }
+            GeminiError::Deserialize => write!(f, "Error deserializing json received from gemini"),
+            GeminiError::Safety => write!(f, "Gemini refused to answer for safety reasons"),
+            GeminiError::Api(message) => write!(f, "{}", message),
+            GeminiError::InvalidJson(error) => {
+                write!(f, "Gemini answered with invalid JSON: {}", error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GeminiError {}
+
+// requests that fail to connect, are rate limited or hit a server error are sent again,
+// waiting backoff before the first retry and twice as long before each next one
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 2,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        RetryPolicy {
+            retries: 0,
+            backoff: Duration::ZERO,
+        }
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff * 2u32.saturating_pow(attempt)
+    }
+}
+
+// a throwaway conversation with only the prompt
+fn one_shot_conversation(
+    instruction: &str,
+    prompt: &str,
+    generation: &GenerationConfig,
+) -> Conversation {
+    let mut conversation = Conversation::default();
+    conversation.set_persona("", instruction);
+    if !generation.is_empty() {
+        conversation.generationConfig = Some(generation.clone());
+    }
+    conversation.add_message(Contents::from_text("user", prompt.to_string()));
+    conversation
+}
+
+async fn read_json<T: DeserializeOwned>(response: reqwest::Response) -> Result<T, GeminiError> {
+    info!("Getting string from POST request response...");
+    let text = response.text().await.map_err(|error| {
+        error!("{}: {}", GeminiError::Body, error.without_url());
+        GeminiError::Body
+    })?;
+
+    info!("Deserializing string from POST request response...");
+    serde_json::from_str(&text).map_err(|error| {
+        error!("{}: {}", GeminiError::Deserialize, error);
+        GeminiError::Deserialize
+    })
+}
+
+fn is_retryable(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+pub struct Gemini {
+    pub api_key: String,
+    // used when a channel has no model set
+    pub model: String,
+    pub base_url: String,
+    // shared by every request, conversational or not
+    pub client: reqwest::Client,
+    pub retry: RetryPolicy,
+}
+
+impl Gemini {
+    // base_url is the api root, for example https://generativelanguage.googleapis.com/v1beta
+    pub fn new(api_key: String, model: String, base_url: &str) -> Self {
+        Gemini {
+            api_key,
+            model,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn url(&self, model: &str, method: &str) -> String {
+        format!("{}/models/{}:{}", self.base_url, model, method)
+    }
+
+    // models that can generate content, without the "models/" prefix
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>, String> {
+        info!("Listing models...");
+        let response = self
+            .client
+            .get(format!("{}/models?pageSize=1000", self.base_url))
+            .header("x-goog-api-key", &self.api_key)
+            .send()
+            .await
+            .map_err(|error| {
+                error!("Error listing models: {}", error.without_url());
+                String::from("Error listing models")
+            })?;
+        let text = response.text().await.map_err(|error| {
+            error!("Error listing models: {}", error.without_url());
+            String::from("Error listing models")
+        })?;
+        let list: ModelList = serde_json::from_str(&text).map_err(|error| {
+            error!("Error deserializing model list: {}", error);
+            String::from("Error deserializing model list")
+        })?;
+        if list.models.is_empty() {
+            return Err(list.error.message.replace(&self.api_key, "API KEY"));
+        }
+        Ok(list
+            .models
+            .into_iter()
+            .filter(|m| {
+                m.supportedGenerationMethods
+                    .iter()
+                    .any(|method| method == "generateContent")
+            })
+            .map(|mut m| {
+                m.name = m.name.trim_start_matches("models/").to_string();
+                m
+            })
+            .collect())
+    }
+
+    // sends the message with the conversation's history, the history is only kept if gemini replied,
+    // message_ids are the discord messages of the prompt so edits and deletes can find it
+    pub async fn send_msg(
+        &self,
+        model: &str,
+        conversation: &mut Conversation,
+        message: String,
+        images: Vec<InlineData>,
+        message_ids: Vec<u64>,
+    ) -> (String, UsageMetadata) {
+        let no_tools = ToolRegistry::default();
+        self.send_msg_with_tools(
+            model,
+            conversation,
+            message,
+            images,
+            message_ids,
+            &no_tools,
+            &ToolContext::default(),
+        )
+        .await
+    }
+
+    // like send_msg, but runs the tools gemini calls and sends their results back
+    // until it answers or the registry's step limit is reached
+    #[allow(clippy::too_many_arguments)]
+    pub async fn send_msg_with_tools(
+        &self,
+        model: &str,
+        conversation: &mut Conversation,
+        message: String,
+        images: Vec<InlineData>,
+        message_ids: Vec<u64>,
+        tools: &ToolRegistry,
+        ctx: &ToolContext,
+    ) -> (String, UsageMetadata) {
+        info!("Forwarding message to {}...", model);
+
+        // instance struct that will store the user's message
+        let mut parts = Vec::new();
+        for image in images {
+            info!("Content type is: {}", image.mimeType);
+            parts.push(Parts {
+                inlineData: Some(image),
+                ..Default::default()
+            });
+        }
+        parts.push(Parts {
+            text: message,
+            ..Default::default()
+        });
+        let user_content = Contents {
+            role: "user".to_string(),
+            parts,
+            message_ids,
+            ..Default::default()
+        };
+
+        info!("Adding user's message to history...");
+        // everything after start is removed if gemini doesn't answer
+        let start = conversation.contents.len();
+        conversation.add_message(user_content);
+        conversation.tools = tools.declarations(ctx);
+
+        let mut usage_metadata = UsageMetadata::default();
+        let mut steps = 0;
+        loop {
+            let mut response_json = match self.generate(model, conversation).await {
+                Ok(response) => response,
+                Err(err) => {
+                    conversation.contents.truncate(start);
+                    return (err.to_string(), UsageMetadata::default());
+                }
+            };
+
+            // if response was success, replies cut off by the token limit are kept and can be continued
+            if !response_json.candidates.is_empty()
+                && (response_json.candidates[0].finishReason == "STOP"
+                    || response_json.candidates[0].finishReason == "MAX_TOKENS")
+            {
+                info!(
+                    "Successful response from gemini: {}",
+                    response_json.candidates[0].finishReason
+                );
+                usage_metadata.add(&response_json.usageMetadata);
+                let candidate = response_json.candidates.remove(0);
+
+                let calls: Vec<FunctionCall> = candidate
+                    .content
+                    .parts
+                    .iter()
+                    .filter_map(|part| part.functionCall.clone())
+                    .collect();
+                if !calls.is_empty() {
+                    if steps >= tools.max_steps {
+                        error!("Too many tool calls, giving up");
+                        conversation.contents.truncate(start);
+                        return (
+                            "Gave up after too many tool calls".to_string(),
+                            UsageMetadata::default(),
+                        );
+                    }
+                    steps += 1;
+                    conversation.add_message(Contents {
+                        role: "model".to_string(),
+                        parts: candidate.content.parts,
+                        ..Default::default()
+                    });
+                    let mut results = Vec::new();
+                    for call in &calls {
+                        results.push(Parts {
+                            functionResponse: Some(tools.call(ctx, call).await),
+                            ..Default::default()
+                        });
+                    }
+                    conversation.add_message(Contents {
+                        role: "function".to_string(),
+                        parts: results,
+                        ..Default::default()
+                    });
+                    continue;
+                }
+
+                let response_text = candidate.content.text();
+                // the tool calls were only needed for this answer, the history keeps the question and the answer
+                conversation.contents.truncate(start + 1);
+                // images are kept while tools run so the answer still sees them, then only the text stays
+                conversation.strip_images();
+
+                let gemini_response = Contents {
+                    role: "model".to_string(),
+                    parts: vec![Parts {
+                        text: response_text.clone(),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                };
+
+                info!("Adding bot's reply to history...");
+                conversation.add_message(gemini_response);
+
+                conversation.delete_old();
+                conversation.token_count = usage_metadata.totalTokenCount;
+                conversation.truncated = candidate.finishReason == "MAX_TOKENS";
+                conversation.replies += 1;
+
+                return (response_text, usage_metadata);
+            }
+            // if safety trigger
+            else if !response_json.candidates.is_empty()
+                && response_json.candidates[0].finishReason == "SAFETY"
+            {
+                conversation.contents.truncate(start);
+                return (
+                    "https://i.imgur.com/DJqE6wq.jpeg".to_string(),
+                    UsageMetadata::default(),
+                );
+            }
+            // other unknown response
+            else {
+                error!("Unknown error: {}", response_json.error.message);
+                let error_message = response_json
+                    .error
+                    .message
+                    .replace(&self.api_key, "API KEY");
+                conversation.contents.truncate(start);
+                return (error_message, UsageMetadata::default());
+            }
+        }
+    }
+
+    // a single request that doesn't use or change any conversation,
+    // for features like translation and summaries that must not touch the chat history
+    pub async fn one_shot(
+        &self,
+        model: &str,
+        instruction: &str,
+        prompt: &str,
+    ) -> Result<(String, UsageMetadata), GeminiError> {
+        let (candidate, usage_metadata) = self
+            .one_shot_candidate(model, instruction, prompt, &GenerationConfig::default())
+            .await?;
+        if candidate.finishReason == "SAFETY" {
+            return Err(GeminiError::Safety);
+        }
+        Ok((candidate.content.text(), usage_metadata))
+    }
+
+    // like one_shot, but also returns answers blocked for safety with their safety ratings
+    pub async fn one_shot_candidate(
+        &self,
+        model: &str,
+        instruction: &str,
+        prompt: &str,
+        generation: &GenerationConfig,
+    ) -> Result<(Candidates, UsageMetadata), GeminiError> {
+        let mut conversation = one_shot_conversation(instruction, prompt, generation);
+        self.candidate(model, &mut conversation).await
+    }
+
+    // one_shot with a file gemini can read, like a pdf or an image
+    pub async fn one_shot_with_file(
+        &self,
+        model: &str,
+        instruction: &str,
+        prompt: &str,
+        file: InlineData,
+    ) -> Result<(String, UsageMetadata), GeminiError> {
+        let mut conversation =
+            one_shot_conversation(instruction, prompt, &GenerationConfig::default());
+        if let Some(turn) = conversation.contents.last_mut() {
+            turn.parts.insert(
+                0,
+                Parts {
+                    inlineData: Some(file),
+                    ..Default::default()
+                },
+            );
+        }
+        let (candidate, usage_metadata) = self.candidate(model, &mut conversation).await?;
+        if candidate.finishReason == "SAFETY" {
+            return Err(GeminiError::Safety);
+        }
+        Ok((candidate.content.text(), usage_metadata))
+    }
+
+    // asks for json shaped like the schema and deserializes it,
+    // an answer that doesn't parse is sent back once with the error to be fixed
+    pub async fn one_shot_json<T: DeserializeOwned>(
+        &self,
+        model: &str,
+        instruction: &str,
+        prompt: &str,
+        schema: serde_json::Value,
+    ) -> Result<(T, UsageMetadata), GeminiError> {
+        let generation = GenerationConfig {
+            responseMimeType: Some(String::from("application/json")),
+            responseSchema: Some(schema),
+            ..Default::default()
+        };
+        let mut conversation = one_shot_conversation(instruction, prompt, &generation);
+        let mut usage_metadata = UsageMetadata::default();
+        let mut attempts = 0;
+        loop {
+            let (candidate, usage) = self.candidate(model, &mut conversation).await?;
+            usage_metadata.add(&usage);
+            if candidate.finishReason == "SAFETY" {
+                return Err(GeminiError::Safety);
+            }
+            let text = candidate.content.text();
+            let error = match serde_json::from_str::<T>(&text) {
+                Ok(value) => return Ok((value, usage_metadata)),
+                Err(error) => error,
+            };
+            error!("Gemini answered with invalid json: {}", error);
+            attempts += 1;
+            if attempts > 1 {
+                return Err(GeminiError::InvalidJson(error.to_string()));
+            }
+            conversation.add_message(Contents::from_text("model", text));
+            conversation.add_message(Contents::from_text(
+                "user",
+                format!(
+                    "That is not valid JSON for the schema: {}. Answer again with only the JSON.",
+                    error
+                ),
+            ));
+        }
+    }
+
+    // the first candidate of a finished or blocked answer
+    async fn candidate(
+        &self,
+        model: &str,
+        conversation: &mut Conversation,
+    ) -> Result<(Candidates, UsageMetadata), GeminiError> {
+        let mut response_json = self.generate(model, conversation).await?;
+        match response_json.candidates.first() {
+            Some(candidate)
+                if candidate.finishReason == "STOP"
+                    || candidate.finishReason == "MAX_TOKENS"
+                    || candidate.finishReason == "SAFETY" =>
+            {
+                Ok((
+                    response_json.candidates.remove(0),
+                    response_json.usageMetadata,
+                ))
+            }
+            _ => {
+                error!("Unknown error: {}", response_json.error.message);
+                Err(GeminiError::Api(
+                    response_json
+                        .error
+                        .message
+                        .replace(&self.api_key, "API KEY"),
+                ))
+            }
+        }
+    }
+
+    // sends the conversation as it is
+    async fn generate(
+        &self,
+        model: &str,
+        conversation: &Conversation,
+    ) -> Result<Response, GeminiError> {
+        let json_to_send = conversation.get_json().map_err(|error| {
+            error!("Error converting to json: {}", error);
+            GeminiError::Serialize
+        })?;
+        info!("size in kb: {}", json_to_send.len() as f32 / 1024.0);
+
+        let response = self.post(model, "generateContent", json_to_send).await?;
+        read_json(response).await
+    }
+
+    // embeddings of the texts in the same order, task_type is RETRIEVAL_DOCUMENT for what is
+    // searched and RETRIEVAL_QUERY for what is searched for
+    pub async fn embed(
+        &self,
+        model: &str,
+        texts: &[String],
+        task_type: &str,
+    ) -> Result<Vec<Vec<f32>>, GeminiError> {
+        let mut embeddings = Vec::new();
+        // the api takes at most 100 texts per request
+        for batch in texts.chunks(100) {
+            info!("Embedding {} texts with {}...", batch.len(), model);
+            let requests: Vec<serde_json::Value> = batch
+                .iter()
+                .map(|text| {
+                    serde_json::json!({
+                        "model": format!("models/{}", model),
+                        "content": {"parts": [{"text": text}]},
+                        "taskType": task_type,
+                    })
+                })
+                .collect();
+            let body = serde_json::json!({ "requests": requests }).to_string();
+            let response = self.post(model, "batchEmbedContents", body).await?;
+            let response_json: EmbeddingResponse = read_json(response).await?;
+            if response_json.embeddings.len() != batch.len() {
+                error!("Error embedding: {}", response_json.error.message);
+                return Err(GeminiError::Api(
+                    response_json
+                        .error
+                        .message
+                        .replace(&self.api_key, "API KEY"),
+                ));
+            }
+            embeddings.extend(
+                response_json
+                    .embeddings
+                    .into_iter()
+                    .map(|embedding| embedding.values),
+            );
+        }
+        Ok(embeddings)
+    }
+
+    // sends the request again as the retry policy allows, the last response is returned as it is
+    async fn post(
+        &self,
+        model: &str,
+        method: &str,
+        body: String,
+    ) -> Result<reqwest::Response, GeminiError> {
+        let mut attempt = 0;
+        loop {
+            info!("Sending POST request...");
+            let result = self
+                .client
+                .post(self.url(model, method))
+                .body(body.clone())
+                .header("Content-Type", "application/json")
+                .header("x-goog-api-key", &self.api_key)
+                .send()
+                .await;
+            let last_attempt = attempt >= self.retry.retries;
+            let delay = self.retry.delay(attempt);
+            match result {
+                Ok(response) if last_attempt || !is_retryable(response.status()) => {
+                    return Ok(response)
+                }
+                Ok(response) => warn!(
+                    "Gemini answered {}, retrying in {:?}",
+                    response.status(),
+                    delay
+                ),
+                Err(error) if last_attempt => {
+                    error!("{}: {}", GeminiError::Request, error.without_url());
+                    return Err(GeminiError::Request);
+                }
+                Err(error) => warn!(
+                    "{}, retrying in {:?}: {}",
+                    GeminiError::Request,
+                    delay,
+                    error.without_url()
+                ),
+            }
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::structs::GenerationConfig;
+
+impl GenerationConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == GenerationConfig::default()
+    }
+
+    // values set in other replace the ones in self
+    pub fn merge(&self, other: &GenerationConfig) -> GenerationConfig {
+        GenerationConfig {
+            temperature: other.temperature.or(self.temperature),
+            topP: other.topP.or(self.topP),
+            topK: other.topK.or(self.topK),
+            maxOutputTokens: other.maxOutputTokens.or(self.maxOutputTokens),
+            stopSequences: if other.stopSequences.is_empty() {
+                self.stopSequences.clone()
+            } else {
+                other.stopSequences.clone()
+            },
+            responseMimeType: other
+                .responseMimeType
+                .clone()
+                .or_else(|| self.responseMimeType.clone()),
+            responseSchema: other
+                .responseSchema
+                .clone()
+                .or_else(|| self.responseSchema.clone()),
+        }
+    }
+
+    pub fn validate(&self, model: &str) -> Result<(), String> {
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                return Err(format!(
+                    "Temperature must be between 0 and 2, got {}",
+                    temperature
+                ));
+            }
+        }
+        if let Some(top_p) = self.topP {
+            if !(0.0..=1.0).contains(&top_p) {
+                return Err(format!("Top-p must be between 0 and 1, got {}", top_p));
+            }
+        }
+        if let Some(top_k) = self.topK {
+            if top_k < 1 {
+                return Err(format!("Top-k must be at least 1, got {}", top_k));
+            }
+        }
+        if let Some(max_tokens) = self.maxOutputTokens {
+            let limit = max_output_tokens(model);
+            if max_tokens < 1 || max_tokens > limit {
+                return Err(format!(
+                    "Max tokens must be between 1 and {} for {}, got {}",
+                    limit, model, max_tokens
+                ));
+            }
+        }
+        if self.stopSequences.len() > 5 {
+            return Err(String::from("At most 5 stop sequences are allowed"));
+        }
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        if self.is_empty() {
+            return String::from("model defaults");
+        }
+        let mut values = Vec::new();
+        if let Some(temperature) = self.temperature {
+            values.push(format!("temp {}", temperature));
+        }
+        if let Some(top_p) = self.topP {
+            values.push(format!("top-p {}", top_p));
+        }
+        if let Some(top_k) = self.topK {
+            values.push(format!("top-k {}", top_k));
+        }
+        if let Some(max_tokens) = self.maxOutputTokens {
+            values.push(format!("max tokens {}", max_tokens));
+        }
+        if !self.stopSequences.is_empty() {
+            values.push(format!("stop {:?}", self.stopSequences));
+        }
+        if let Some(mime_type) = &self.responseMimeType {
+            values.push(format!("output {}", mime_type));
+        }
+        values.join(", ")
+    }
+}
+
+// output token limit of the known models
+pub fn max_output_tokens(model: &str) -> i32 {
+    if model.starts_with("gemini-1.5") || model.starts_with("gemini-2") {
+        8192
+    } else {
+        2048
+    }
+}
+
+// takes "--temp 0.2", "--top-p", "--top-k", "--max-tokens" and "--stop" out of the text,
+// returns the remaining text and the parsed values
+pub fn parse_flags(text: &str) -> Result<(String, GenerationConfig), String> {
+    let mut config = GenerationConfig::default();
+    let mut remaining: Vec<&str> = Vec::new();
+    let mut words = text.split(' ');
+
+    while let Some(word) = words.next() {
+        let flag = match word {
+            "--temp" | "--temperature" | "--top-p" | "--top-k" | "--max-tokens" | "--stop" => word,
+            _ => {
+                remaining.push(word);
+                continue;
+            }
+        };
+        let Some(value) = words.next().filter(|v| !v.is_empty()) else {
+            return Err(format!("Missing value for {}", flag));
+        };
+        let invalid = || format!("Invalid value for {}: {}", flag, value);
+        match flag {
+            "--temp" | "--temperature" => {
+                config.temperature = Some(value.parse().map_err(|_| invalid())?)
+            }
+            "--top-p" => config.topP = Some(value.parse().map_err(|_| invalid())?),
+            "--top-k" => config.topK = Some(value.parse().map_err(|_| invalid())?),
+            "--max-tokens" => config.maxOutputTokens = Some(value.parse().map_err(|_| invalid())?),
+            _ => config.stopSequences.push(value.to_string()),
+        }
+    }
+    Ok((remaining.join(" ").trim().to_string(), config))
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::docs::{chunk_document, cosine_similarity, Chunk, CHUNK_CHARS};
+use crate::persist::{load_json, save_json};
+
+// reference material an admin attached to a channel
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct KbEntry {
+    // numbered per channel, shown in !kb list and in reply footers
+    pub id: u64,
+    pub title: String,
+    // "note", "pin" or "file"
+    pub kind: String,
+    pub chars: usize,
+    pub added_by: u64,
+    // unix timestamp
+    pub created: i64,
+    pub chunks: Vec<Chunk>,
+}
+
+impl KbEntry {
+    pub fn label(&self) -> String {
+        format!("#{} {}", self.id, self.title)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ChannelKb {
+    pub next_id: u64,
+    pub entries: Vec<KbEntry>,
+}
+
+impl ChannelKb {
+    pub fn total_chars(&self) -> usize {
+        self.entries.iter().map(|entry| entry.chars).sum()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct KnowledgeBase {
+    pub channels: HashMap<u64, ChannelKb>,
+    #[serde(skip)]
+    path: String,
+}
+
+impl KnowledgeBase {
+    pub fn load(path: &str) -> Self {
+        let mut kb: KnowledgeBase = load_json(path, "knowledge base");
+        kb.path = path.to_string();
+        kb
+    }
+
+    pub fn save(&self) {
+        save_json(&self.path, self, "knowledge base", false);
+    }
+
+    pub fn channel(&self, channel_id: u64) -> Option<&ChannelKb> {
+        self.channels.get(&channel_id)
+    }
+
+    // checks that chars more characters fit in the channel, replaced entries don't count
+    pub fn check_size(
+        &self,
+        channel_id: u64,
+        chars: usize,
+        replaced_kind: Option<&str>,
+        max_chars: usize,
+    ) -> Result<(), String> {
+        let used: usize = self
+            .channel(channel_id)
+            .map(|kb| {
+                kb.entries
+                    .iter()
+                    .filter(|entry| Some(entry.kind.as_str()) != replaced_kind)
+                    .map(|entry| entry.chars)
+                    .sum()
+            })
+            .unwrap_or(0);
+        if used + chars > max_chars {
+            Err(format!(
+                "The knowledge base of this channel would have {} characters, the limit is {}",
+                used + chars,
+                max_chars
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    // adds the entry and returns its id
+    pub fn add(&mut self, channel_id: u64, mut entry: KbEntry) -> u64 {
+        let kb = self.channels.entry(channel_id).or_default();
+        kb.next_id += 1;
+        entry.id = kb.next_id;
+        kb.entries.push(entry);
+        kb.next_id
+    }
+
+    pub fn remove(&mut self, channel_id: u64, id: u64) -> Option<KbEntry> {
+        let kb = self.channels.get_mut(&channel_id)?;
+        let index = kb.entries.iter().position(|entry| entry.id == id)?;
+        Some(kb.entries.remove(index))
+    }
+
+    // removes the entries of a kind, like the pins before they are imported again
+    pub fn remove_kind(&mut self, channel_id: u64, kind: &str) {
+        if let Some(kb) = self.channels.get_mut(&channel_id) {
+            kb.entries.retain(|entry| entry.kind != kind);
+        }
+    }
+
+    pub fn clear(&mut self, channel_id: u64) {
+        self.channels.remove(&channel_id);
+    }
+
+    // the chunks closest to the query with the entry they belong to, best first
+    pub fn search(
+        &self,
+        channel_id: u64,
+        query: &[f32],
+        top_k: usize,
+        min_score: f32,
+    ) -> Vec<(&KbEntry, &Chunk)> {
+        let Some(kb) = self.channel(channel_id) else {
+            return Vec::new();
+        };
+        let mut scored: Vec<(f32, &KbEntry, &Chunk)> = kb
+            .entries
+            .iter()
+            .flat_map(|entry| entry.chunks.iter().map(move |chunk| (entry, chunk)))
+            .map(|(entry, chunk)| (cosine_similarity(query, &chunk.embedding), entry, chunk))
+            .filter(|(score, _, _)| *score >= min_score)
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored
+            .into_iter()
+            .take(top_k)
+            .map(|(_, entry, chunk)| (entry, chunk))
+            .collect()
+    }
+}
+
+// an entry with its text chunked, the chunks still need their embeddings
+pub fn new_entry(title: &str, kind: &str, text: &str, added_by: u64, now: i64) -> KbEntry {
+    KbEntry {
+        id: 0,
+        title: title.to_string(),
+        kind: kind.to_string(),
+        chars: text.chars().count(),
+        added_by,
+        created: now,
+        chunks: chunk_document(title, text, CHUNK_CHARS),
+    }
+}
+
+// "!kb add Rules: be nice" -> ("Rules", "be nice"), without a title the start of the text is used
+pub fn parse_note(argument: &str) -> (String, String) {
+    match argument.split_once(':') {
+        // "https://..." has no title, the colon must be followed by a space
+        Some((title, text))
+            if !title.trim().is_empty()
+                && title.len() <= 60
+                && !title.contains('\n')
+                && text.starts_with(char::is_whitespace) =>
+        {
+            (title.trim().to_string(), text.trim().to_string())
+        }
+        _ => {
+            let text = argument.trim();
+            let mut title: String = text.lines().next().unwrap_or("").chars().take(40).collect();
+            if title.len() < text.len() {
+                title.push_str("...");
+            }
+            (title, text.to_string())
+        }
+    }
+}
@@ -0,0 +1,21 @@
+pub mod bot;
+pub mod config;
+pub mod discord;
+pub mod discord_tools;
+pub mod docs;
+pub mod gemini;
+pub mod generation;
+pub mod knowledge;
+pub mod memory;
+pub mod moderation;
+pub mod persist;
+pub mod persona;
+pub mod redact;
+pub mod repl;
+pub mod settings;
+pub mod structs;
+pub mod summarize;
+pub mod tools;
+pub mod transcript;
+pub mod translate;
+pub mod usage;
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use serenity::async_trait;
+use tokio::sync::Mutex;
+
+use crate::persist::{load_json, save_json};
+use crate::tools::{Tool, ToolContext};
+
+// facts kept per user, and how long one can be
+pub const MAX_FACTS: usize = 50;
+pub const MAX_FACT_CHARS: usize = 300;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Fact {
+    pub text: String,
+    // unix timestamp
+    pub created: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct UserMemory {
+    // users opt in with !memory on, nothing is saved before that
+    pub enabled: bool,
+    pub facts: Vec<Fact>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct MemoryStore {
+    pub users: HashMap<u64, UserMemory>,
+    #[serde(skip)]
+    path: String,
+}
+
+impl MemoryStore {
+    pub fn load(path: &str) -> Self {
+        let mut store: MemoryStore = load_json(path, "memory file");
+        store.path = path.to_string();
+        store
+    }
+
+    pub fn save(&self) {
+        save_json(&self.path, self, "memory file", false);
+    }
+
+    pub fn is_enabled(&self, user_id: u64) -> bool {
+        self.users.get(&user_id).is_some_and(|user| user.enabled)
+    }
+
+    // turning memory off forgets every fact
+    pub fn set_enabled(&mut self, user_id: u64, enabled: bool) {
+        if enabled {
+            self.users.entry(user_id).or_default().enabled = true;
+        } else {
+            self.users.remove(&user_id);
+        }
+    }
+
+    pub fn facts(&self, user_id: u64) -> &[Fact] {
+        self.users
+            .get(&user_id)
+            .map(|user| user.facts.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn add(&mut self, user_id: u64, text: &str, now: i64) -> Result<(), String> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(String::from("The fact is empty"));
+        }
+        if text.chars().count() > MAX_FACT_CHARS {
+            return Err(format!(
+                "Facts can be at most {} characters long",
+                MAX_FACT_CHARS
+            ));
+        }
+        let user = match self.users.get_mut(&user_id) {
+            Some(user) if user.enabled => user,
+            _ => return Err(String::from("Memory is off, turn it on with !memory on")),
+        };
+        if user.facts.iter().any(|fact| fact.text == text) {
+            return Ok(());
+        }
+        if user.facts.len() >= MAX_FACTS {
+            return Err(format!(
+                "You can have at most {} facts, forget some with !memory forget",
+                MAX_FACTS
+            ));
+        }
+        user.facts.push(Fact {
+            text: text.to_string(),
+            created: now,
+        });
+        Ok(())
+    }
+
+    // index starts at 1, like in !memory list
+    pub fn forget(&mut self, user_id: u64, index: usize) -> Option<Fact> {
+        let user = self.users.get_mut(&user_id)?;
+        if index == 0 || index > user.facts.len() {
+            return None;
+        }
+        Some(user.facts.remove(index - 1))
+    }
+
+    pub fn clear(&mut self, user_id: u64) {
+        if let Some(user) = self.users.get_mut(&user_id) {
+            user.facts.clear();
+        }
+    }
+
+    // everything stored about the user as json
+    pub fn export(&self, user_id: u64) -> String {
+        let user = self.users.get(&user_id).cloned().unwrap_or_default();
+        serde_json::to_string_pretty(&json!({
+            "user_id": user_id.to_string(),
+            "enabled": user.enabled,
+            "facts": user.facts,
+        }))
+        .unwrap_or_default()
+    }
+
+    // added to the system instruction of the user's requests, None without facts
+    pub fn instruction(&self, user_id: u64) -> Option<String> {
+        let facts = self.facts(user_id);
+        if !self.is_enabled(user_id) || facts.is_empty() {
+            return None;
+        }
+        let mut instruction = format!(
+            "The message is from <@{}>, who asked you to remember these facts about them:",
+            user_id
+        );
+        for fact in facts {
+            instruction.push_str(&format!("\n- {}", fact.text));
+        }
+        Some(instruction)
+    }
+}
+
+// lets gemini save what users tell it about themselves
+pub struct RememberFact {
+    pub store: Arc<Mutex<MemoryStore>>,
+}
+
+#[async_trait]
+impl Tool for RememberFact {
+    fn name(&self) -> &str {
+        "remember_fact"
+    }
+
+    fn description(&self) -> &str {
+        "Saves a lasting fact about the user who is asking, like what to call them or what they use, \
+        to remember it in later conversations. Only use it for facts the user wants remembered."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "fact": {
+                    "type": "string",
+                    "description": "The fact in one short sentence, like \"Uses Arch Linux\""
+                }
+            },
+            "required": ["fact"]
+        })
+    }
+
+    async fn call(&self, ctx: &ToolContext, args: &Value) -> Result<Value, String> {
+        let fact = args["fact"].as_str().unwrap_or("");
+        let mut store = self.store.lock().await;
+        store.add(ctx.user_id, fact, chrono::Utc::now().timestamp())?;
+        store.save();
+        Ok(json!("Saved"))
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::error;
+
+use crate::gemini::{Gemini, GeminiError};
+use crate::structs::{GenerationConfig, SafetyRatings, UsageMetadata};
+
+const INSTRUCTION: &str = "You are a moderator of a Discord server. \
+Classify the user's message as harassment, spam, scam or none. \
+Harassment is insulting, threatening or hateful towards people. \
+Spam is flooding, unsolicited advertising or repeated mass mentions. \
+Scams are phishing links, fake giveaways, fake nitro or requests for money or account details. \
+Banter between friends and strong opinions are none. \
+Confidence is from 0 to 1 and the reason is a short sentence.";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Verdict {
+    // harassment, spam, scam, or none
+    pub category: String,
+    pub confidence: f64,
+    pub reason: String,
+}
+
+impl Default for Verdict {
+    fn default() -> Self {
+        Verdict {
+            category: String::from("none"),
+            confidence: 0.0,
+            reason: String::new(),
+        }
+    }
+}
+
+impl Verdict {
+    pub fn is_flagged(&self, threshold: f64) -> bool {
+        self.category != "none" && self.confidence >= threshold
+    }
+}
+
+// the json gemini answers with
+fn verdict_schema() -> Value {
+    json!({
+        "type": "OBJECT",
+        "properties": {
+            "category": {
+                "type": "STRING",
+                "enum": ["none", "harassment", "spam", "scam"]
+            },
+            "confidence": {"type": "NUMBER"},
+            "reason": {"type": "STRING"}
+        },
+        "required": ["category", "confidence", "reason"]
+    })
+}
+
+// reads the model's answer, which may be wrapped in a markdown code block
+pub fn parse_verdict(text: &str) -> Option<Verdict> {
+    let text = text.trim();
+    let text = text
+        .strip_prefix("```json")
+        .or_else(|| text.strip_prefix("```"))
+        .and_then(|text| text.strip_suffix("```"))
+        .unwrap_or(text);
+    match serde_json::from_str::<Verdict>(text.trim()) {
+        Ok(verdict) => Some(verdict),
+        Err(error) => {
+            error!("Error parsing moderation verdict: {}", error);
+            None
+        }
+    }
+}
+
+// the strongest safety rating gemini gave the message, as a verdict
+pub fn rating_verdict(ratings: &[SafetyRatings]) -> Verdict {
+    ratings
+        .iter()
+        .map(|rating| Verdict {
+            category: match rating.category.as_str() {
+                "HARM_CATEGORY_HARASSMENT" | "HARM_CATEGORY_HATE_SPEECH" => {
+                    String::from("harassment")
+                }
+                category => category
+                    .trim_start_matches("HARM_CATEGORY_")
+                    .to_lowercase()
+                    .replace('_', " "),
+            },
+            confidence: match rating.probability.as_str() {
+                "HIGH" => 0.9,
+                "MEDIUM" => 0.6,
+                "LOW" => 0.3,
+                _ => 0.0,
+            },
+            reason: format!(
+                "Gemini rated it {} for {}",
+                rating.probability.to_lowercase(),
+                rating.category.to_lowercase()
+            ),
+        })
+        .filter(|verdict| verdict.confidence > 0.0)
+        .fold(Verdict::default(), |strongest, verdict| {
+            if verdict.confidence > strongest.confidence {
+                verdict
+            } else {
+                strongest
+            }
+        })
+}
+
+// classifies the message, the safety ratings count when they are more certain than the model
+pub async fn classify(
+    gemini: &Gemini,
+    model: &str,
+    content: &str,
+) -> Result<(Verdict, UsageMetadata), GeminiError> {
+    let generation = GenerationConfig {
+        responseMimeType: Some(String::from("application/json")),
+        responseSchema: Some(verdict_schema()),
+        ..Default::default()
+    };
+    // not one_shot_json, answers blocked for safety still have their ratings
+    let (candidate, usage_metadata) = gemini
+        .one_shot_candidate(model, INSTRUCTION, content, &generation)
+        .await?;
+    let answered = parse_verdict(&candidate.content.text()).unwrap_or_default();
+    let rated = rating_verdict(&candidate.safetyRatings);
+    if rated.confidence > answered.confidence {
+        Ok((rated, usage_metadata))
+    } else {
+        Ok((answered, usage_metadata))
+    }
+}
+
+// how many messages of each channel are classified per minute, the rest are skipped
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    per_minute: usize,
+    // timestamps of the last checks of each channel
+    checks: HashMap<u64, VecDeque<i64>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: usize) -> Self {
+        RateLimiter {
+            per_minute,
+            checks: HashMap::new(),
+        }
+    }
+
+    pub fn allow(&mut self, channel_id: u64, now: i64) -> bool {
+        let checks = self.checks.entry(channel_id).or_default();
+        while checks.front().is_some_and(|time| *time <= now - 60) {
+            checks.pop_front();
+        }
+        if checks.len() >= self.per_minute {
+            return false;
+        }
+        checks.push_back(now);
+        true
+    }
+}
+
+// link that opens the message in discord
+pub fn jump_link(guild_id: u64, channel_id: u64, message_id: u64) -> String {
+    format!(
+        "https://discord.com/channels/{}/{}/{}",
+        guild_id, channel_id, message_id
+    )
+}
@@ -0,0 +1,42 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{error, info};
+
+// reads a store saved with save_json, an empty path or a missing file starts empty
+pub fn load_json<T: DeserializeOwned + Default>(path: &str, what: &str) -> T {
+    if path.is_empty() {
+        return T::default();
+    }
+    match std::fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_else(|error| {
+            error!("Error parsing {} {}: {}", what, path, error);
+            T::default()
+        }),
+        Err(_) => {
+            info!("No {} found at {}, starting empty", what, path);
+            T::default()
+        }
+    }
+}
+
+// stores without a path are only kept in memory, pretty is for files people edit by hand
+pub fn save_json<T: Serialize>(path: &str, value: &T, what: &str, pretty: bool) {
+    if path.is_empty() {
+        return;
+    }
+    let json = if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    };
+    let json = match json {
+        Ok(json) => json,
+        Err(error) => {
+            error!("Error serializing {}: {}", what, error);
+            return;
+        }
+    };
+    if let Err(error) = std::fs::write(path, json) {
+        error!("Error writing {} {}: {}", what, path, error);
+    }
+}
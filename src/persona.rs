@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::structs::GenerationConfig;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Persona {
+    pub name: String,
+    pub system_instruction: String,
+    pub generation: GenerationConfig,
+}
+
+// personas are loaded from a json array, "default" always exists and has no system instruction
+#[derive(Debug, Default)]
+pub struct Personas {
+    pub personas: Vec<Persona>,
+}
+
+impl Personas {
+    pub fn load(path: &str) -> Self {
+        let mut personas: Vec<Persona> = match std::fs::read_to_string(path) {
+            Ok(text) => match serde_json::from_str(&text) {
+                Ok(personas) => personas,
+                Err(error) => {
+                    error!("Error parsing personas file {}: {}", path, error);
+                    Vec::new()
+                }
+            },
+            Err(_) => {
+                info!("No personas file found at {}", path);
+                Vec::new()
+            }
+        };
+        if !personas.iter().any(|p| p.name == "default") {
+            personas.insert(
+                0,
+                Persona {
+                    name: String::from("default"),
+                    system_instruction: String::from(""),
+                    generation: GenerationConfig::default(),
+                },
+            );
+        }
+        info!("Loaded {} personas", personas.len());
+        Personas { personas }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Persona> {
+        self.personas.iter().find(|p| p.name == name)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.personas.iter().map(|p| p.name.as_str()).collect()
+    }
+}
@@ -0,0 +1,70 @@
+use std::io;
+use std::sync::Arc;
+
+use tracing_subscriber::fmt::MakeWriter;
+
+const MASK: &str = "[REDACTED]";
+
+// wraps the log writer and masks secrets in everything written through it
+#[derive(Clone)]
+pub struct RedactingMakeWriter<M> {
+    inner: M,
+    secrets: Arc<Vec<String>>,
+}
+
+impl<M> RedactingMakeWriter<M> {
+    pub fn new(inner: M, secrets: Vec<String>) -> Self {
+        // empty secrets would mask between every character
+        let secrets = secrets.into_iter().filter(|s| !s.is_empty()).collect();
+        RedactingMakeWriter {
+            inner,
+            secrets: Arc::new(secrets),
+        }
+    }
+}
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for RedactingMakeWriter<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter {
+            inner: self.inner.make_writer(),
+            secrets: self.secrets.clone(),
+        }
+    }
+}
+
+pub struct RedactingWriter<W> {
+    inner: W,
+    secrets: Arc<Vec<String>>,
+}
+
+impl<W: io::Write> io::Write for RedactingWriter<W> {
+    // the fmt layer writes each event at once, so secrets are never split between writes
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        if self
+            .secrets
+            .iter()
+            .any(|secret| text.contains(secret.as_str()))
+        {
+            self.inner
+                .write_all(redact(&text, &self.secrets).as_bytes())?;
+        } else {
+            self.inner.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub fn redact(text: &str, secrets: &[String]) -> String {
+    let mut text = text.to_string();
+    for secret in secrets.iter().filter(|s| !s.is_empty()) {
+        text = text.replace(secret.as_str(), MASK);
+    }
+    text
+}
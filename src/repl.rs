@@ -0,0 +1,118 @@
+use std::io::Write;
+
+use serenity::async_trait;
+
+use crate::bot::{Attachment, Bot, IncomingMessage, Transport, OWNER_ID};
+use crate::gemini::SUPPORTED_IMAGE_TYPES;
+
+const HELP: &str = "/reset - clears the conversation
+/image <path> - attaches an image to the next message
+/persona [name] - lists personas or switches to one
+/quit - exits";
+
+// the terminal has a single conversation
+const CHANNEL_ID: u64 = 0;
+const BOT_ID: u64 = 1;
+
+// prints the bot's replies to the terminal, attachments are local files
+struct TerminalTransport;
+
+#[async_trait]
+impl Transport for TerminalTransport {
+    async fn reply(&self, text: &str) -> Option<u64> {
+        println!("{}", text);
+        None
+    }
+
+    async fn say(&self, text: &str) {
+        println!("{}", text);
+    }
+
+    async fn typing(&self) {}
+
+    async fn set_presence(&self, _status: &str) {}
+
+    async fn download(&self, attachment: &Attachment) -> Result<Vec<u8>, String> {
+        std::fs::read(&attachment.url)
+            .map_err(|error| format!("Error reading {}: {}", attachment.url, error))
+    }
+}
+
+// chats with gemini from the terminal using the same pipeline as discord
+pub async fn run(bot: Bot) {
+    let transport = TerminalTransport;
+    let mut attachment: Option<Attachment> = None;
+
+    println!(
+        "Chatting with {}, type /help for commands",
+        bot.gemini.model
+    );
+    loop {
+        print!("> ");
+        let _ = std::io::stdout().flush();
+
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(error) => {
+                println!("Error reading input: {}", error);
+                break;
+            }
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "/quit" | "/exit" => break,
+            "/help" => println!("{}", HELP),
+            "/reset" => {
+                bot.reset_conversation(CHANNEL_ID).await;
+                attachment = None;
+                println!("Conversation has been reset!");
+            }
+            "/image" => match local_attachment(argument.trim()) {
+                Ok(file) => {
+                    println!("Attached {} to the next message", file.filename);
+                    attachment = Some(file);
+                }
+                Err(error) => println!("{}", error),
+            },
+            "/persona" => println!("{}", bot.switch_persona(CHANNEL_ID, argument.trim()).await),
+            _ => {
+                let msg = IncomingMessage {
+                    message_id: 0,
+                    author_id: OWNER_ID,
+                    channel_id: CHANNEL_ID,
+                    guild_id: None,
+                    content: line.to_string(),
+                    bot_id: BOT_ID,
+                    mentions_bot: true,
+                    attachments: attachment.take().into_iter().collect(),
+                    referenced: Vec::new(),
+                    local: true,
+                };
+                bot.handle_message(&transport, &msg).await;
+            }
+        }
+    }
+}
+
+fn local_attachment(path: &str) -> Result<Attachment, String> {
+    let extension = path.rsplit('.').next().unwrap_or("").to_lowercase();
+    let mime_type = format!("image/{}", extension);
+    if !SUPPORTED_IMAGE_TYPES.contains(&mime_type.as_str()) {
+        return Err(String::from("Unsupported attachment type"));
+    }
+    if !std::path::Path::new(path).is_file() {
+        return Err(format!("File not found: {}", path));
+    }
+    Ok(Attachment {
+        url: path.to_string(),
+        filename: path.rsplit('/').next().unwrap_or(path).to_string(),
+        content_type: Some(mime_type),
+    })
+}
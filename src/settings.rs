@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::persist::{load_json, save_json};
+use crate::structs::GenerationConfig;
+
+// settings admins changed at runtime for a channel
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ChannelSettings {
+    // None uses the default model or automatic routing
+    pub model: Option<String>,
+    pub generation: GenerationConfig,
+    // messages are checked for harassment, spam and scams
+    pub moderated: bool,
+}
+
+// settings admins changed at runtime for a whole guild
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct GuildSettings {
+    pub generation: GenerationConfig,
+    // channel of the guild flagged messages are posted to, moderation is off without it
+    pub mod_log: Option<u64>,
+}
+
+// a thread the bot started, it answers every message in it
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct BotThread {
+    pub parent_id: u64,
+    // unix timestamp of the last message in the thread
+    pub last_activity: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Settings {
+    pub channels: HashMap<u64, ChannelSettings>,
+    pub guilds: HashMap<u64, GuildSettings>,
+    pub threads: HashMap<u64, BotThread>,
+    // users allowed to talk to the bot in direct messages
+    pub dm_users: Vec<u64>,
+    #[serde(skip)]
+    path: String,
+    // thread activity changed since the file was written
+    #[serde(skip)]
+    unsaved: bool,
+}
+
+impl Settings {
+    pub fn load(path: &str) -> Self {
+        let mut settings: Settings = load_json(path, "settings file");
+        settings.path = path.to_string();
+        settings
+    }
+
+    pub fn save(&self) {
+        save_json(&self.path, self, "settings file", true);
+    }
+
+    // writes what changed without being saved right away
+    pub fn flush(&mut self) {
+        if self.unsaved {
+            self.unsaved = false;
+            self.save();
+        }
+    }
+
+    // true if the channel is one of the bot's threads, which then counts as active,
+    // the activity changes with every message so it is only written by flush
+    pub fn touch_thread(&mut self, channel_id: u64, now: i64) -> bool {
+        match self.threads.get_mut(&channel_id) {
+            Some(thread) => {
+                thread.last_activity = now;
+                self.unsaved = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn channel(&self, channel_id: u64) -> ChannelSettings {
+        self.channels.get(&channel_id).cloned().unwrap_or_default()
+    }
+
+    pub fn channel_mut(&mut self, channel_id: u64) -> &mut ChannelSettings {
+        self.channels.entry(channel_id).or_default()
+    }
+
+    pub fn guild(&self, guild_id: u64) -> GuildSettings {
+        self.guilds.get(&guild_id).cloned().unwrap_or_default()
+    }
+
+    pub fn guild_mut(&mut self, guild_id: u64) -> &mut GuildSettings {
+        self.guilds.entry(guild_id).or_default()
+    }
+}
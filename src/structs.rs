@@ -1,10 +1,18 @@
+#![allow(clippy::derivable_impls)]
+
+use std::ops::Range;
+
 use serde::{Deserialize, Serialize};
 
+// turns a conversation keeps, and how many of them pins can keep so new exchanges have room
+pub const MAX_TURNS: usize = 20;
+pub const MAX_PINNED_TURNS: usize = 10;
+
 #[derive(Debug, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 #[serde(default)]
 pub struct Response {
-    pub candidates: [Candidates; 1],
+    pub candidates: Vec<Candidates>,
 
     pub usageMetadata: UsageMetadata,
     pub error: Error,
@@ -13,7 +21,7 @@ pub struct Response {
 impl Default for Response {
     fn default() -> Self {
         Response {
-            candidates: [Candidates::default()],
+            candidates: vec![Candidates::default()],
             usageMetadata: UsageMetadata::default(),
             error: Error::default(),
         }
@@ -27,7 +35,7 @@ pub struct Candidates {
     pub content: Content,
     pub finishReason: String,
     pub index: i32,
-    pub safetyRatings: [SafetyRatings; 4],
+    pub safetyRatings: Vec<SafetyRatings>,
 }
 
 impl Default for Candidates {
@@ -36,24 +44,7 @@ impl Default for Candidates {
             content: Content::default(),
             finishReason: String::from("Unknown"),
             index: -1,
-            safetyRatings: [
-                SafetyRatings {
-                    category: String::from(""),
-                    probability: String::from(""),
-                },
-                SafetyRatings {
-                    category: String::from(""),
-                    probability: String::from(""),
-                },
-                SafetyRatings {
-                    category: String::from(""),
-                    probability: String::from(""),
-                },
-                SafetyRatings {
-                    category: String::from(""),
-                    probability: String::from(""),
-                },
-            ],
+            safetyRatings: Vec::new(),
         }
     }
 }
@@ -63,14 +54,25 @@ impl Default for Candidates {
 #[serde(default)]
 pub struct Content {
     pub role: String,
-    pub parts: [Parts; 1],
+    pub parts: Vec<Parts>,
+}
+
+impl Content {
+    // joins the text of all parts
+    pub fn text(&self) -> String {
+        self.parts
+            .iter()
+            .map(|part| part.text.as_str())
+            .collect::<Vec<&str>>()
+            .join("")
+    }
 }
 
 impl Default for Content {
     fn default() -> Self {
         Content {
             role: String::from(""),
-            parts: [Parts::default()],
+            parts: vec![Parts::default()],
         }
     }
 }
@@ -80,29 +82,85 @@ impl Default for Content {
 #[serde(default)]
 pub struct Contents {
     pub role: String,
-    pub parts: Parts,
+    pub parts: Vec<Parts>,
+    // discord messages of the turn, the prompt for user turns and the reply chunks for model turns
+    #[serde(skip)]
+    pub message_ids: Vec<u64>,
+    // pinned turns are never dropped to make room
+    #[serde(skip)]
+    pub pinned: bool,
 }
 
 impl Default for Contents {
     fn default() -> Self {
         Contents {
             role: String::from(""),
-            parts: Parts::default(),
+            parts: Vec::new(),
+            message_ids: Vec::new(),
+            pinned: false,
+        }
+    }
+}
+
+impl Contents {
+    // a turn with a single text part
+    pub fn from_text(role: &str, text: impl Into<String>) -> Self {
+        Contents {
+            role: role.to_string(),
+            parts: vec![Parts {
+                text: text.into(),
+                ..Default::default()
+            }],
+            ..Default::default()
         }
     }
+
+    // joins the text of all parts
+    pub fn text(&self) -> String {
+        self.parts
+            .iter()
+            .map(|part| part.text.as_str())
+            .collect::<Vec<&str>>()
+            .join("")
+    }
+
+    // about four characters per token, good enough to see which turns are big
+    pub fn estimated_tokens(&self) -> usize {
+        self.text().chars().count().div_ceil(4)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 #[serde(default)]
 pub struct Conversation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub systemInstruction: Option<Contents>,
     pub contents: Vec<Contents>,
     pub safety_settings: [SafetySettings; 4],
+    // set for each request from the guild, channel, persona and message settings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generationConfig: Option<GenerationConfig>,
+    // set for each request from the tools the user may use
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tools>,
+    // name of the persona whose system instruction is used
+    #[serde(skip)]
+    pub persona: String,
+    // tokens used by the last request, not sent to gemini
+    #[serde(skip)]
+    pub token_count: i32,
+    // counts gemini's replies, buttons of older replies can't change the history
+    #[serde(skip)]
+    pub replies: u64,
+    // the last reply was cut off by the output token limit
+    #[serde(skip)]
+    pub truncated: bool,
 }
 
 impl Conversation {
     pub fn add_message(&mut self, msg: Contents) {
-        let _ = self.contents.push(msg);
+        self.contents.push(msg);
     }
 
     pub fn revert(&mut self) {
@@ -110,17 +168,142 @@ impl Conversation {
         let _ = self.contents.pop();
     }
 
+    // removes inline images from the history so they are only sent once
+    pub fn strip_images(&mut self) {
+        for content in self.contents.iter_mut() {
+            content.parts.retain(|part| part.inlineData.is_none());
+        }
+    }
+
+    pub fn set_persona(&mut self, name: &str, system_instruction: &str) {
+        self.persona = name.to_string();
+        self.systemInstruction = if system_instruction.is_empty() {
+            None
+        } else {
+            Some(Contents {
+                role: String::from("user"),
+                parts: vec![Parts {
+                    text: system_instruction.to_string(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            })
+        };
+    }
+
     pub fn get_json(&self) -> Result<String, serde_json::Error> {
         serde_json::to_string(&self)
     }
 
     pub fn reset_conversation(&mut self) {
-        let _ = self.contents.clear();
+        self.contents.clear();
+        self.token_count = 0;
+    }
+
+    // removes the last user message and gemini's reply to it, returns the user message
+    pub fn pop_exchange(&mut self) -> Option<Contents> {
+        let len = self.contents.len();
+        if len < 2
+            || self.contents[len - 1].role != "model"
+            || self.contents[len - 2].role != "user"
+        {
+            return None;
+        }
+        self.contents.pop();
+        self.truncated = false;
+        // the buttons of the popped reply are stale even if asking again fails
+        self.replies += 1;
+        self.contents.pop()
+    }
+
+    // index of the turn that came from the discord message
+    pub fn find_message(&self, message_id: u64) -> Option<usize> {
+        self.contents
+            .iter()
+            .position(|content| content.message_ids.contains(&message_id))
     }
 
+    // removes the user turn and gemini's reply to it, index can point at either of them,
+    // returns the range of turns that were removed
+    pub fn remove_exchange(&mut self, index: usize) -> Range<usize> {
+        let Some(exchange) = self
+            .exchanges()
+            .into_iter()
+            .find(|exchange| exchange.contains(&index))
+        else {
+            return index..index;
+        };
+        self.contents.drain(exchange.clone());
+        if exchange.start == self.contents.len() {
+            self.truncated = false;
+        }
+        // the buttons of the latest reply would now point at another exchange
+        self.replies += 1;
+        exchange
+    }
+
+    // the turns grouped into exchanges, a question with its reply, turns without one are alone
+    fn exchanges(&self) -> Vec<Range<usize>> {
+        let mut exchanges = Vec::new();
+        let mut start = 0;
+        while start < self.contents.len() {
+            let paired = self.contents[start].role == "user"
+                && self
+                    .contents
+                    .get(start + 1)
+                    .is_some_and(|content| content.role == "model");
+            let end = if paired { start + 2 } else { start + 1 };
+            exchanges.push(start..end);
+            start = end;
+        }
+        exchanges
+    }
+
+    fn is_pinned(&self, exchange: &Range<usize>) -> bool {
+        self.contents[exchange.clone()]
+            .iter()
+            .any(|content| content.pinned)
+    }
+
+    // turns kept by pins, a pinned question keeps its reply and the other way around
+    pub fn pinned_turns(&self) -> usize {
+        self.exchanges()
+            .iter()
+            .filter(|exchange| self.is_pinned(exchange))
+            .map(|exchange| exchange.len())
+            .sum()
+    }
+
+    // pins a turn so delete_old keeps its exchange, refused when pins would keep too many turns
+    pub fn pin_turn(&mut self, index: usize) -> Result<(), String> {
+        if self.contents[index].pinned {
+            return Ok(());
+        }
+        self.contents[index].pinned = true;
+        if self.pinned_turns() > MAX_PINNED_TURNS {
+            self.contents[index].pinned = false;
+            return Err(format!(
+                "Pins can keep at most {} turns, unpin some with !history unpin",
+                MAX_PINNED_TURNS
+            ));
+        }
+        Ok(())
+    }
+
+    // drops the oldest exchanges that aren't pinned, the latest exchange is always kept
     pub fn delete_old(&mut self) {
-        while self.contents.len() >= 20 {
-            self.contents.remove(0);
+        while self.contents.len() >= MAX_TURNS {
+            let mut exchanges = self.exchanges();
+            exchanges.pop();
+            match exchanges
+                .into_iter()
+                .find(|exchange| !self.is_pinned(exchange))
+            {
+                Some(exchange) => {
+                    self.contents.drain(exchange);
+                }
+                None => break,
+            }
         }
     }
 }
@@ -128,6 +311,7 @@ impl Conversation {
 impl Default for Conversation {
     fn default() -> Self {
         Conversation {
+            systemInstruction: None,
             contents: Vec::new(),
             safety_settings: [
                 SafetySettings {
@@ -147,6 +331,12 @@ impl Default for Conversation {
                     threshold: String::from("BLOCK_NONE"),
                 },
             ],
+            generationConfig: None,
+            tools: Vec::new(),
+            persona: String::from("default"),
+            token_count: 0,
+            replies: 0,
+            truncated: false,
         }
     }
 }
@@ -155,20 +345,80 @@ impl Default for Conversation {
 #[allow(non_snake_case)]
 #[serde(default)]
 pub struct Parts {
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inlineData: Option<InlineData>,
+    // gemini asking to run a tool
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub functionCall: Option<FunctionCall>,
+    // the result of a tool, sent back to gemini
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub functionResponse: Option<FunctionResponse>,
 }
 
 impl Default for Parts {
     fn default() -> Self {
         Parts {
             text: String::from(""),
+            inlineData: None,
+            functionCall: None,
+            functionResponse: None,
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct FunctionCall {
+    pub name: String,
+    pub args: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct FunctionResponse {
+    pub name: String,
+    pub response: serde_json::Value,
+}
+
+// the tools gemini may call, parameters is a json schema
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[allow(non_snake_case)]
+#[serde(default)]
+pub struct Tools {
+    pub functionDeclarations: Vec<FunctionDeclaration>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 #[serde(default)]
+pub struct InlineData {
+    pub mimeType: String,
+    // base64 encoded
+    pub data: String,
+}
+
+impl Default for InlineData {
+    fn default() -> Self {
+        InlineData {
+            mimeType: String::from(""),
+            data: String::from(""),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[allow(non_snake_case, dead_code)]
+#[serde(default)]
 pub struct FileData {
     pub mimeType: String,
     pub fileUri: String,
@@ -184,35 +434,18 @@ impl Default for FileData {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-#[allow(non_snake_case)]
+#[allow(non_snake_case, dead_code)]
 #[serde(default)]
 pub struct PromptFeedback {
     pub blockReason: String,
-    pub safetyRatings: [SafetyRatings; 4],
+    pub safetyRatings: Vec<SafetyRatings>,
 }
 
 impl Default for PromptFeedback {
     fn default() -> Self {
         PromptFeedback {
             blockReason: String::from(""),
-            safetyRatings: [
-                SafetyRatings {
-                    category: String::from(""),
-                    probability: String::from(""),
-                },
-                SafetyRatings {
-                    category: String::from(""),
-                    probability: String::from(""),
-                },
-                SafetyRatings {
-                    category: String::from(""),
-                    probability: String::from(""),
-                },
-                SafetyRatings {
-                    category: String::from(""),
-                    probability: String::from(""),
-                },
-            ],
+            safetyRatings: Vec::new(),
         }
     }
 }
@@ -234,7 +467,7 @@ impl Default for SafetyRatings {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 #[serde(default)]
 pub struct UsageMetadata {
@@ -253,6 +486,20 @@ impl Default for UsageMetadata {
     }
 }
 
+impl UsageMetadata {
+    // adds the tokens of another request of the same answer,
+    // the total stays the size of the last request since that is the context used
+    pub fn add(&mut self, other: &UsageMetadata) {
+        if self.totalTokenCount == -1 {
+            *self = other.clone();
+            return;
+        }
+        self.promptTokenCount += other.promptTokenCount;
+        self.candidatesTokenCount += other.candidatesTokenCount;
+        self.totalTokenCount = other.totalTokenCount;
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[allow(non_snake_case)]
 #[serde(default)]
@@ -288,3 +535,93 @@ impl Default for SafetySettings {
         }
     }
 }
+
+// answer of batchEmbedContents, one embedding per request
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct EmbeddingResponse {
+    pub embeddings: Vec<Embedding>,
+    pub error: Error,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Embedding {
+    pub values: Vec<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+#[serde(default)]
+pub struct ModelList {
+    pub models: Vec<ModelInfo>,
+    pub error: Error,
+}
+
+impl Default for ModelList {
+    fn default() -> Self {
+        ModelList {
+            models: Vec::new(),
+            error: Error::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+#[serde(default)]
+pub struct ModelInfo {
+    pub name: String,
+    pub displayName: String,
+    pub inputTokenLimit: i32,
+    pub outputTokenLimit: i32,
+    pub supportedGenerationMethods: Vec<String>,
+}
+
+impl Default for ModelInfo {
+    fn default() -> Self {
+        ModelInfo {
+            name: String::from(""),
+            displayName: String::from(""),
+            inputTokenLimit: -1,
+            outputTokenLimit: -1,
+            supportedGenerationMethods: Vec::new(),
+        }
+    }
+}
+
+// unset values are left to gemini's defaults
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+#[serde(default)]
+pub struct GenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topP: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topK: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maxOutputTokens: Option<i32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stopSequences: Vec<String>,
+    // "application/json" makes gemini answer with json, shaped like responseSchema if it is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub responseMimeType: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub responseSchema: Option<serde_json::Value>,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        GenerationConfig {
+            temperature: None,
+            topP: None,
+            topK: None,
+            maxOutputTokens: None,
+            stopSequences: Vec::new(),
+            responseMimeType: None,
+            responseSchema: None,
+        }
+    }
+}
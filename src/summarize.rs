@@ -0,0 +1,131 @@
+use tracing::info;
+
+use crate::gemini::Gemini;
+use crate::structs::UsageMetadata;
+
+// messages fetched when no amount is given, and the most that can be asked for
+pub const DEFAULT_MESSAGES: usize = 100;
+pub const MAX_MESSAGES: usize = 1000;
+
+const MAP_INSTRUCTION: &str = "Summarize this part of a Discord conversation. \
+Keep who said what when it matters, skip greetings and small talk. Be concise.";
+const REDUCE_INSTRUCTION: &str = "These are summaries of consecutive parts of a Discord \
+conversation, oldest first. Combine them into one concise summary.";
+
+// a message of the channel's history, oldest messages come first
+#[derive(Debug, Clone, Default)]
+pub struct HistoryMessage {
+    pub author_name: String,
+    pub content: String,
+    // unix timestamp
+    pub timestamp: i64,
+}
+
+// "" -> the default amount, "200" -> the last 200 messages,
+// "2h" / "30m" / "1d" or "YYYY-MM-DD HH:MM" -> everything since then, up to MAX_MESSAGES
+pub fn parse_range(argument: &str, now: i64) -> Result<(usize, Option<i64>), String> {
+    let argument = argument.trim();
+    if argument.is_empty() {
+        return Ok((DEFAULT_MESSAGES, None));
+    }
+    if let Ok(count) = argument.parse::<usize>() {
+        if count == 0 || count > MAX_MESSAGES {
+            return Err(format!(
+                "Amount of messages must be between 1 and {}",
+                MAX_MESSAGES
+            ));
+        }
+        return Ok((count, None));
+    }
+    let usage = || {
+        String::from("Usage: !summarize [amount of messages | 30m | 2h | 1d | YYYY-MM-DD HH:MM]")
+    };
+    let units = [('m', 60), ('h', 60 * 60), ('d', 24 * 60 * 60)];
+    for (unit, seconds) in units {
+        if let Some(Ok(amount)) = argument.strip_suffix(unit).map(str::parse::<i64>) {
+            // the amount is user input, it must not overflow or reach back before 1970
+            let since = amount
+                .checked_mul(seconds)
+                .and_then(|duration| now.checked_sub(duration))
+                .filter(|since| amount > 0 && *since >= 0)
+                .ok_or_else(usage)?;
+            return Ok((MAX_MESSAGES, Some(since)));
+        }
+    }
+    let since = chrono::NaiveDateTime::parse_from_str(argument, "%Y-%m-%d %H:%M")
+        .or_else(|_| {
+            chrono::NaiveDate::parse_from_str(argument, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default())
+        })
+        .map_err(|_| usage())?;
+    Ok((MAX_MESSAGES, Some(since.and_utc().timestamp())))
+}
+
+// "[2024-05-01 12:30] name: text"
+pub fn format_history(messages: &[HistoryMessage]) -> Vec<String> {
+    messages
+        .iter()
+        .filter(|msg| !msg.content.trim().is_empty())
+        .map(|msg| {
+            let time = chrono::DateTime::from_timestamp(msg.timestamp, 0)
+                .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            format!("[{}] {}: {}", time, msg.author_name, msg.content)
+        })
+        .collect()
+}
+
+// joins lines into chunks of at most max_chars, a longer line gets a chunk of its own
+pub fn chunk_lines(lines: &[String], max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + line.len() + 1 > max_chars {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+// summarizes every chunk, then summarizes the summaries until one is left
+pub async fn summarize(
+    gemini: &Gemini,
+    model: &str,
+    lines: &[String],
+    max_chars: usize,
+) -> Result<(String, UsageMetadata), String> {
+    let mut usage_metadata = UsageMetadata::default();
+    let mut texts = chunk_lines(lines, max_chars);
+    if texts.is_empty() {
+        return Err(String::from("No messages to summarize"));
+    }
+    let mut instruction = MAP_INSTRUCTION;
+    loop {
+        info!("Summarizing {} chunks...", texts.len());
+        let mut summaries = Vec::new();
+        for text in &texts {
+            let (summary, usage) = gemini
+                .one_shot(model, instruction, text)
+                .await
+                .map_err(|error| error.to_string())?;
+            usage_metadata.add(&usage);
+            summaries.push(summary);
+        }
+        if summaries.len() == 1 {
+            return Ok((summaries.remove(0), usage_metadata));
+        }
+        texts = chunk_lines(&summaries, max_chars);
+        // summaries too long to share a chunk are still combined in pairs
+        if texts.len() >= summaries.len() {
+            texts = summaries.chunks(2).map(|pair| pair.join("\n\n")).collect();
+        }
+        instruction = REDUCE_INSTRUCTION;
+    }
+}
@@ -0,0 +1,134 @@
+use serde_json::{json, Value};
+use serenity::async_trait;
+use tracing::{error, info};
+
+use crate::structs::{FunctionCall, FunctionDeclaration, FunctionResponse, Tools};
+
+// who asked, tools use it to decide what they may show
+#[derive(Debug, Clone, Default)]
+pub struct ToolContext {
+    pub user_id: u64,
+    pub channel_id: u64,
+    // None in direct messages
+    pub guild_id: Option<u64>,
+    pub is_admin: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToolPermission {
+    Everyone,
+    Admin,
+}
+
+// a function gemini can call, parameters is a json schema of its arguments
+#[async_trait]
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+    fn description(&self) -> &str;
+    fn parameters(&self) -> Value;
+    fn permission(&self) -> ToolPermission {
+        ToolPermission::Everyone
+    }
+    async fn call(&self, ctx: &ToolContext, args: &Value) -> Result<Value, String>;
+}
+
+#[derive(Default)]
+pub struct ToolRegistry {
+    tools: Vec<Box<dyn Tool>>,
+    // names of tools turned off in the config
+    disabled: Vec<String>,
+    // how many rounds of tool calls an answer can take
+    pub max_steps: usize,
+}
+
+impl ToolRegistry {
+    pub fn new(disabled: Vec<String>, max_steps: usize) -> Self {
+        ToolRegistry {
+            tools: Vec::new(),
+            disabled,
+            max_steps,
+        }
+    }
+
+    // the tools every transport has
+    pub fn with_builtins(disabled: Vec<String>, max_steps: usize) -> Self {
+        let mut registry = ToolRegistry::new(disabled, max_steps);
+        registry.register(CurrentTime);
+        registry
+    }
+
+    pub fn register(&mut self, tool: impl Tool + 'static) {
+        info!("Registered tool {}", tool.name());
+        self.tools.push(Box::new(tool));
+    }
+
+    fn allowed(&self, ctx: &ToolContext) -> impl Iterator<Item = &dyn Tool> {
+        let is_admin = ctx.is_admin;
+        self.tools
+            .iter()
+            .map(|tool| tool.as_ref())
+            .filter(move |tool| !self.disabled.iter().any(|name| name == tool.name()))
+            .filter(move |tool| is_admin || tool.permission() == ToolPermission::Everyone)
+    }
+
+    // what is sent to gemini, empty if the user can't use any tool
+    pub fn declarations(&self, ctx: &ToolContext) -> Vec<Tools> {
+        let declarations: Vec<FunctionDeclaration> = self
+            .allowed(ctx)
+            .map(|tool| FunctionDeclaration {
+                name: tool.name().to_string(),
+                description: tool.description().to_string(),
+                parameters: tool.parameters(),
+            })
+            .collect();
+        if declarations.is_empty() {
+            Vec::new()
+        } else {
+            vec![Tools {
+                functionDeclarations: declarations,
+            }]
+        }
+    }
+
+    // errors are given to gemini as the result so it can tell the user
+    pub async fn call(&self, ctx: &ToolContext, call: &FunctionCall) -> FunctionResponse {
+        let response = match self.allowed(ctx).find(|tool| tool.name() == call.name) {
+            Some(tool) => {
+                info!("Calling tool {}", call.name);
+                match tool.call(ctx, &call.args).await {
+                    Ok(result) => json!({ "result": result }),
+                    Err(err) => {
+                        error!("Tool {} failed: {}", call.name, err);
+                        json!({ "error": err })
+                    }
+                }
+            }
+            None => json!({ "error": format!("Unknown tool: {}", call.name) }),
+        };
+        FunctionResponse {
+            name: call.name.clone(),
+            response,
+        }
+    }
+}
+
+pub struct CurrentTime;
+
+#[async_trait]
+impl Tool for CurrentTime {
+    fn name(&self) -> &str {
+        "current_time"
+    }
+
+    fn description(&self) -> &str {
+        "Returns the current date and time in UTC"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({ "type": "object", "properties": {} })
+    }
+
+    async fn call(&self, _ctx: &ToolContext, _args: &Value) -> Result<Value, String> {
+        Ok(json!(chrono::Utc::now().to_rfc3339()))
+    }
+}
@@ -0,0 +1,149 @@
+use serde_json::{json, Value};
+
+use crate::structs::Contents;
+
+// the formats !export writes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Markdown,
+    Json,
+    Html,
+}
+
+impl Format {
+    // "" -> markdown, "json" -> json, unknown formats are None
+    pub fn parse(name: &str) -> Option<Format> {
+        match name.trim().to_lowercase().as_str() {
+            "" | "md" | "markdown" => Some(Format::Markdown),
+            "json" => Some(Format::Json),
+            "html" => Some(Format::Html),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Markdown => "md",
+            Format::Json => "json",
+            Format::Html => "html",
+        }
+    }
+}
+
+fn speaker(role: &str) -> &str {
+    match role {
+        "user" => "User",
+        "model" => "Gemini",
+        role => role,
+    }
+}
+
+// the turns as a transcript in the format
+pub fn export(contents: &[Contents], format: Format) -> String {
+    // old turns are dropped one at a time, so the history can start with a reply to a dropped
+    // question, the transcript starts at the first question so it can be imported again
+    let first_question = contents
+        .iter()
+        .position(|content| content.role == "user")
+        .unwrap_or(contents.len());
+    let contents = &contents[first_question..];
+    match format {
+        Format::Markdown => to_markdown(contents),
+        Format::Json => to_json(contents),
+        Format::Html => to_html(contents),
+    }
+}
+
+pub fn to_markdown(contents: &[Contents]) -> String {
+    let mut markdown = String::from("# Conversation\n");
+    for content in contents {
+        markdown.push_str(&format!(
+            "\n**{}**\n\n{}\n",
+            speaker(&content.role),
+            content.text().trim()
+        ));
+    }
+    markdown
+}
+
+// the same format gemini takes, so the file can be imported again
+pub fn to_json(contents: &[Contents]) -> String {
+    serde_json::to_string_pretty(&json!({ "contents": contents })).unwrap_or_default()
+}
+
+pub fn to_html(contents: &[Contents]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Conversation</title>\n\
+        <style>body { font-family: sans-serif; max-width: 50em; margin: auto; } \
+        .turn { white-space: pre-wrap; padding: 0.5em; margin: 0.5em 0; border-radius: 0.5em; } \
+        .user { background: #eef; } .model { background: #efe; }</style>\n\
+        </head>\n<body>\n<h1>Conversation</h1>\n",
+    );
+    for content in contents {
+        html.push_str(&format!(
+            "<div class=\"turn {}\"><b>{}</b>\n{}</div>\n",
+            escape_html(&content.role),
+            escape_html(speaker(&content.role)),
+            escape_html(content.text().trim())
+        ));
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// reads a json transcript, {"contents": [...]} or just the list of turns, and checks that
+// it only has text turns alternating between the user and the model
+pub fn parse_transcript(text: &str) -> Result<Vec<Contents>, String> {
+    let value: Value = serde_json::from_str(text)
+        .map_err(|error| format!("The file isn't valid JSON: {}", error))?;
+    let turns = match value {
+        Value::Object(mut object) => object.remove("contents").unwrap_or(Value::Null),
+        value => value,
+    };
+    let contents: Vec<Contents> = serde_json::from_value(turns)
+        .map_err(|error| format!("The file isn't a transcript: {}", error))?;
+    if contents.is_empty() {
+        return Err(String::from("The transcript has no turns"));
+    }
+    for (index, content) in contents.iter().enumerate() {
+        let number = index + 1;
+        if content.role != "user" && content.role != "model" {
+            return Err(format!(
+                "Turn {} has the role \"{}\", only user and model are allowed",
+                number, content.role
+            ));
+        }
+        let expected = if index % 2 == 0 { "user" } else { "model" };
+        if content.role != expected {
+            return Err(format!(
+                "Turn {} should be from the {}, turns alternate starting with the user",
+                number, expected
+            ));
+        }
+        let text_only = content.parts.iter().all(|part| {
+            part.inlineData.is_none()
+                && part.functionCall.is_none()
+                && part.functionResponse.is_none()
+        });
+        if !text_only || content.text().trim().is_empty() {
+            return Err(format!("Turn {} has to be text", number));
+        }
+    }
+    if contents
+        .last()
+        .is_some_and(|content| content.role != "model")
+    {
+        return Err(String::from(
+            "The transcript has to end with a reply from the model",
+        ));
+    }
+    Ok(contents)
+}
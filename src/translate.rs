@@ -0,0 +1,96 @@
+use crate::gemini::{Gemini, GeminiError};
+use crate::structs::UsageMetadata;
+
+const INSTRUCTION: &str = "You are a translator. Translate the user's message into {language}. \
+Keep the formatting, mentions, links and emojis as they are. \
+Only answer with the translation, without notes or explanations.";
+
+// country of a flag emoji and the language it stands for
+const FLAG_LANGUAGES: &[(&str, &str)] = &[
+    ("AR", "Spanish"),
+    ("AT", "German"),
+    ("AU", "English"),
+    ("BE", "Dutch"),
+    ("BG", "Bulgarian"),
+    ("BR", "Brazilian Portuguese"),
+    ("CA", "English"),
+    ("CH", "German"),
+    ("CL", "Spanish"),
+    ("CN", "Simplified Chinese"),
+    ("CO", "Spanish"),
+    ("CZ", "Czech"),
+    ("DE", "German"),
+    ("DK", "Danish"),
+    ("EE", "Estonian"),
+    ("EG", "Arabic"),
+    ("ES", "Spanish"),
+    ("FI", "Finnish"),
+    ("FR", "French"),
+    ("GB", "English"),
+    ("GR", "Greek"),
+    ("HK", "Traditional Chinese"),
+    ("HR", "Croatian"),
+    ("HU", "Hungarian"),
+    ("ID", "Indonesian"),
+    ("IE", "English"),
+    ("IL", "Hebrew"),
+    ("IN", "Hindi"),
+    ("IR", "Persian"),
+    ("IS", "Icelandic"),
+    ("IT", "Italian"),
+    ("JP", "Japanese"),
+    ("KR", "Korean"),
+    ("LT", "Lithuanian"),
+    ("LV", "Latvian"),
+    ("MX", "Spanish"),
+    ("MY", "Malay"),
+    ("NL", "Dutch"),
+    ("NO", "Norwegian"),
+    ("NZ", "English"),
+    ("PE", "Spanish"),
+    ("PH", "Filipino"),
+    ("PK", "Urdu"),
+    ("PL", "Polish"),
+    ("PT", "Portuguese"),
+    ("RO", "Romanian"),
+    ("RS", "Serbian"),
+    ("RU", "Russian"),
+    ("SA", "Arabic"),
+    ("SE", "Swedish"),
+    ("SI", "Slovenian"),
+    ("SK", "Slovak"),
+    ("TH", "Thai"),
+    ("TR", "Turkish"),
+    ("TW", "Traditional Chinese"),
+    ("UA", "Ukrainian"),
+    ("US", "English"),
+    ("VN", "Vietnamese"),
+    ("ZA", "English"),
+];
+
+// "🇫🇷" -> Some("French"), None if the emoji isn't a known flag
+pub fn flag_language(emoji: &str) -> Option<&'static str> {
+    let country: String = emoji
+        .chars()
+        .map(|c| match c as u32 {
+            // regional indicator symbols A to Z
+            0x1F1E6..=0x1F1FF => char::from_u32(c as u32 - 0x1F1E6 + 'A' as u32),
+            _ => None,
+        })
+        .collect::<Option<String>>()?;
+    FLAG_LANGUAGES
+        .iter()
+        .find(|(code, _)| *code == country)
+        .map(|(_, language)| *language)
+}
+
+// translates without touching any conversation
+pub async fn translate(
+    gemini: &Gemini,
+    model: &str,
+    language: &str,
+    text: &str,
+) -> Result<(String, UsageMetadata), GeminiError> {
+    let instruction = INSTRUCTION.replace("{language}", language);
+    gemini.one_shot(model, &instruction, text).await
+}
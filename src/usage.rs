@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ModelPrice;
+use crate::persist::{load_json, save_json};
+
+// the user moderation is recorded under, nobody asked for it
+pub const MODERATION_USER: u64 = 0;
+
+// token counts of one user in one channel with one model on one day
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct UsageRecord {
+    pub date: String,
+    pub user_id: u64,
+    pub channel_id: u64,
+    pub guild_id: u64,
+    pub model: String,
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub candidate_tokens: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct UsageStore {
+    pub records: Vec<UsageRecord>,
+    #[serde(skip)]
+    path: String,
+    // records changed since the file was written
+    #[serde(skip)]
+    unsaved: bool,
+}
+
+// who the usage belongs to, guild_id is 0 in direct messages
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UsageKey {
+    pub user_id: u64,
+    pub channel_id: u64,
+    pub guild_id: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Totals {
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub candidate_tokens: i64,
+}
+
+impl Totals {
+    fn add(&mut self, record: &UsageRecord) {
+        self.requests += record.requests;
+        self.prompt_tokens += record.prompt_tokens;
+        self.candidate_tokens += record.candidate_tokens;
+    }
+
+    pub fn total_tokens(&self) -> i64 {
+        self.prompt_tokens + self.candidate_tokens
+    }
+
+    pub fn cost(&self, price: &ModelPrice) -> f64 {
+        (self.prompt_tokens as f64 * price.input + self.candidate_tokens as f64 * price.output)
+            / 1_000_000.0
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct UsageReport {
+    pub period: String,
+    pub total: Totals,
+    pub models: HashMap<String, Totals>,
+    pub users: HashMap<u64, Totals>,
+    pub channels: HashMap<u64, Totals>,
+}
+
+impl UsageStore {
+    pub fn load(path: &str) -> Self {
+        let mut store: UsageStore = load_json(path, "usage file");
+        store.path = path.to_string();
+        store
+    }
+
+    pub fn save(&self) {
+        save_json(&self.path, self, "usage file", false);
+    }
+
+    // every request is recorded, so the file is only written from time to time
+    pub fn flush(&mut self) {
+        if self.unsaved {
+            self.unsaved = false;
+            self.save();
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        date: &str,
+        key: UsageKey,
+        model: &str,
+        prompt_tokens: i64,
+        candidate_tokens: i64,
+    ) {
+        let existing = self.records.iter_mut().find(|r| {
+            r.date == date
+                && r.user_id == key.user_id
+                && r.channel_id == key.channel_id
+                && r.guild_id == key.guild_id
+                && r.model == model
+        });
+        match existing {
+            Some(record) => {
+                record.requests += 1;
+                record.prompt_tokens += prompt_tokens;
+                record.candidate_tokens += candidate_tokens;
+            }
+            None => self.records.push(UsageRecord {
+                date: date.to_string(),
+                user_id: key.user_id,
+                channel_id: key.channel_id,
+                guild_id: key.guild_id,
+                model: model.to_string(),
+                requests: 1,
+                prompt_tokens,
+                candidate_tokens,
+            }),
+        }
+        self.unsaved = true;
+    }
+
+    // period is a date prefix, "2024-08-21" for a day or "2024-08" for a month
+    pub fn report(&self, period: &str, filter: impl Fn(&UsageRecord) -> bool) -> UsageReport {
+        let mut report = UsageReport {
+            period: period.to_string(),
+            ..Default::default()
+        };
+        for record in self
+            .records
+            .iter()
+            .filter(|r| r.date.starts_with(period) && filter(r))
+        {
+            report.total.add(record);
+            report
+                .models
+                .entry(record.model.clone())
+                .or_default()
+                .add(record);
+            report.users.entry(record.user_id).or_default().add(record);
+            report
+                .channels
+                .entry(record.channel_id)
+                .or_default()
+                .add(record);
+        }
+        report
+    }
+}
+
+impl UsageReport {
+    pub fn format(&self, prices: &HashMap<String, ModelPrice>) -> String {
+        if self.total.requests == 0 {
+            return format!("No usage recorded for {}", self.period);
+        }
+        let mut text = format!(
+            "**Usage for {}**\n{} requests, {} prompt + {} candidate = {} tokens\n",
+            self.period,
+            self.total.requests,
+            self.total.prompt_tokens,
+            self.total.candidate_tokens,
+            self.total.total_tokens()
+        );
+
+        let mut cost = 0.0;
+        let mut priced = false;
+        text.push_str("\n**Models**\n");
+        for (model, totals) in sorted(&self.models) {
+            text.push_str(&format!(
+                "`{}`: {} requests, {} tokens",
+                model,
+                totals.requests,
+                totals.total_tokens()
+            ));
+            if let Some(price) = prices.get(model) {
+                let model_cost = totals.cost(price);
+                cost += model_cost;
+                priced = true;
+                text.push_str(&format!(" (${:.4})", model_cost));
+            }
+            text.push('\n');
+        }
+        if priced {
+            text.push_str(&format!("Estimated cost: ${:.4}\n", cost));
+        }
+
+        text.push_str("\n**Top users**\n");
+        for (user_id, totals) in sorted(&self.users).into_iter().take(5) {
+            let user = if *user_id == MODERATION_USER {
+                String::from("Moderation")
+            } else {
+                format!("<@{}>", user_id)
+            };
+            text.push_str(&format!("{}: {} tokens\n", user, totals.total_tokens()));
+        }
+
+        text.push_str("\n**Top channels**\n");
+        for (channel_id, totals) in sorted(&self.channels).into_iter().take(5) {
+            text.push_str(&format!(
+                "<#{}>: {} tokens\n",
+                channel_id,
+                totals.total_tokens()
+            ));
+        }
+        text
+    }
+}
+
+// sorts by total tokens, highest first
+fn sorted<K>(map: &HashMap<K, Totals>) -> Vec<(&K, &Totals)> {
+    let mut entries: Vec<(&K, &Totals)> = map.iter().collect();
+    entries.sort_by_key(|(_, totals)| std::cmp::Reverse(totals.total_tokens()));
+    entries
+}
@@ -0,0 +1,252 @@
+// local http server that replays canned gemini responses
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use rust_discord_bot::structs::Contents;
+
+// a text turn for building conversations and transcripts
+pub fn turn(role: &str, text: &str) -> Contents {
+    Contents::from_text(role, text)
+}
+
+pub struct CannedResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).expect("request body is not json")
+    }
+}
+
+pub struct MockGemini {
+    pub base_url: String,
+    responses: Arc<Mutex<VecDeque<CannedResponse>>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockGemini {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1beta", listener.local_addr().unwrap());
+        let responses: Arc<Mutex<VecDeque<CannedResponse>>> = Default::default();
+        let requests: Arc<Mutex<Vec<RecordedRequest>>> = Default::default();
+
+        let server_responses = responses.clone();
+        let server_requests = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let request = read_request(&mut stream).await;
+                server_requests.lock().unwrap().push(request);
+                let response = server_responses
+                    .lock()
+                    .unwrap()
+                    .pop_front()
+                    .unwrap_or_else(|| error(500, "INTERNAL", "no canned response left"));
+                let raw = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.status,
+                    response.body.len(),
+                    response.body
+                );
+                let _ = stream.write_all(raw.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+
+        MockGemini {
+            base_url,
+            responses,
+            requests,
+        }
+    }
+
+    pub fn push(&self, response: CannedResponse) {
+        self.responses.lock().unwrap().push_back(response);
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn read_request(stream: &mut tokio::net::TcpStream) -> RecordedRequest {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        let read = stream.read(&mut chunk).await.unwrap_or(0);
+        if read == 0 {
+            break buffer.len();
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(position) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break position + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or("");
+    let mut request_parts = request_line.split(' ');
+    let method = request_parts.next().unwrap_or("").to_string();
+    let path = request_parts.next().unwrap_or("").to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    let content_length = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    while buffer.len() < header_end + content_length {
+        let read = stream.read(&mut chunk).await.unwrap_or(0);
+        if read == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let body = String::from_utf8_lossy(&buffer[header_end..]).to_string();
+
+    RecordedRequest {
+        method,
+        path,
+        headers,
+        body,
+    }
+}
+
+pub fn text(reply: &str) -> CannedResponse {
+    finished(reply, "STOP")
+}
+
+pub fn finished(reply: &str, finish_reason: &str) -> CannedResponse {
+    CannedResponse {
+        status: 200,
+        body: serde_json::json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [{"text": reply}]},
+                "finishReason": finish_reason,
+                "index": 0
+            }],
+            "usageMetadata": {
+                "promptTokenCount": 10,
+                "candidatesTokenCount": 5,
+                "totalTokenCount": 15
+            }
+        })
+        .to_string(),
+    }
+}
+
+// gemini asking to run a tool
+pub fn function_call(name: &str, args: serde_json::Value) -> CannedResponse {
+    CannedResponse {
+        status: 200,
+        body: serde_json::json!({
+            "candidates": [{
+                "content": {
+                    "role": "model",
+                    "parts": [{"functionCall": {"name": name, "args": args}}]
+                },
+                "finishReason": "STOP",
+                "index": 0
+            }],
+            "usageMetadata": {
+                "promptTokenCount": 10,
+                "candidatesTokenCount": 5,
+                "totalTokenCount": 15
+            }
+        })
+        .to_string(),
+    }
+}
+
+pub fn safety() -> CannedResponse {
+    CannedResponse {
+        status: 200,
+        body: serde_json::json!({
+            "candidates": [{
+                "finishReason": "SAFETY",
+                "index": 0,
+                "safetyRatings": [
+                    {"category": "HARM_CATEGORY_HARASSMENT", "probability": "HIGH"}
+                ]
+            }]
+        })
+        .to_string(),
+    }
+}
+
+pub fn error(status: u16, code: &str, message: &str) -> CannedResponse {
+    CannedResponse {
+        status,
+        body: serde_json::json!({
+            "error": {"code": status, "message": message, "status": code}
+        })
+        .to_string(),
+    }
+}
+
+pub fn raw(status: u16, body: &str) -> CannedResponse {
+    CannedResponse {
+        status,
+        body: body.to_string(),
+    }
+}
+
+pub fn models(names: &[&str]) -> CannedResponse {
+    let models: Vec<serde_json::Value> = names
+        .iter()
+        .map(|name| {
+            serde_json::json!({
+                "name": format!("models/{}", name),
+                "displayName": name,
+                "inputTokenLimit": 1048576,
+                "outputTokenLimit": 8192,
+                "supportedGenerationMethods": ["generateContent", "countTokens"]
+            })
+        })
+        .collect();
+    CannedResponse {
+        status: 200,
+        body: serde_json::json!({ "models": models }).to_string(),
+    }
+}
+
+// batchEmbedContents answer, one embedding per text
+pub fn embeddings(vectors: &[&[f32]]) -> CannedResponse {
+    let embeddings: Vec<serde_json::Value> = vectors
+        .iter()
+        .map(|values| serde_json::json!({ "values": values }))
+        .collect();
+    CannedResponse {
+        status: 200,
+        body: serde_json::json!({ "embeddings": embeddings }).to_string(),
+    }
+}
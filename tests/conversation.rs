@@ -0,0 +1,126 @@
+mod common;
+
+use common::turn;
+use rust_discord_bot::bot::history_page;
+use rust_discord_bot::structs::Conversation;
+
+fn conversation(turns: usize) -> Conversation {
+    let mut conversation = Conversation::default();
+    for i in 0..turns {
+        let role = if i % 2 == 0 { "user" } else { "model" };
+        conversation.add_message(turn(role, &format!("turn {}", i)));
+    }
+    conversation
+}
+
+fn texts(conversation: &Conversation) -> Vec<String> {
+    conversation
+        .contents
+        .iter()
+        .map(|content| content.text())
+        .collect()
+}
+
+#[test]
+fn delete_old_drops_whole_exchanges() {
+    let mut conversation = conversation(20);
+    conversation.contents[0].pinned = true;
+    // a pinned reply keeps its question
+    conversation.contents[3].pinned = true;
+
+    conversation.delete_old();
+
+    let texts = texts(&conversation);
+    assert_eq!(texts.len(), 18);
+    assert_eq!(
+        texts[..6],
+        ["turn 0", "turn 1", "turn 2", "turn 3", "turn 6", "turn 7"]
+    );
+    let roles: Vec<&str> = conversation
+        .contents
+        .iter()
+        .map(|content| content.role.as_str())
+        .collect();
+    assert!(roles.chunks(2).all(|pair| pair == ["user", "model"]));
+}
+
+#[test]
+fn delete_old_keeps_the_latest_exchange() {
+    let mut conversation = conversation(20);
+    for content in conversation.contents[..18].iter_mut() {
+        content.pinned = true;
+    }
+
+    conversation.delete_old();
+
+    assert_eq!(conversation.contents.len(), 20);
+    assert_eq!(conversation.contents[19].text(), "turn 19");
+}
+
+#[test]
+fn pins_leave_room_for_new_exchanges() {
+    let mut conversation = conversation(20);
+    for index in [0, 2, 4, 6, 8] {
+        conversation.pin_turn(index).unwrap();
+    }
+
+    // the question of a pinned reply is already kept
+    assert!(conversation.pin_turn(1).is_ok());
+    assert_eq!(
+        conversation.pin_turn(10).unwrap_err(),
+        "Pins can keep at most 10 turns, unpin some with !history unpin"
+    );
+    assert!(!conversation.contents[10].pinned);
+    assert_eq!(conversation.pinned_turns(), 10);
+
+    conversation.delete_old();
+
+    let texts = texts(&conversation);
+    assert_eq!(texts.len(), 18);
+    assert_eq!(texts[10], "turn 12");
+    assert_eq!(texts[17], "turn 19");
+}
+
+#[test]
+fn removing_the_last_exchange_clears_truncated() {
+    let mut conversation = conversation(4);
+    conversation.truncated = true;
+
+    assert_eq!(conversation.remove_exchange(1), 0..2);
+    assert!(conversation.truncated);
+    assert_eq!(texts(&conversation), ["turn 2", "turn 3"]);
+    assert_eq!(conversation.remove_exchange(0), 0..2);
+    assert!(!conversation.truncated);
+    assert_eq!(conversation.remove_exchange(0), 0..0);
+    assert_eq!(conversation.replies, 2);
+}
+
+#[test]
+fn turns_without_a_partner_are_removed_alone() {
+    let mut conversation = conversation(4);
+    conversation.contents.remove(0);
+
+    assert_eq!(conversation.remove_exchange(0), 0..1);
+    assert_eq!(texts(&conversation), ["turn 2", "turn 3"]);
+}
+
+#[test]
+fn history_is_paged() {
+    let mut conversation = conversation(12);
+    conversation.contents[11] = turn("model", &format!("{}\nend", "word ".repeat(30)));
+    conversation.contents[11].pinned = true;
+
+    let first = history_page(&conversation.contents, 1);
+    let second = history_page(&conversation.contents, 5);
+
+    assert!(first.starts_with("**#1** user · ~2 tokens\n> turn 0\n**#2** model"));
+    assert!(first.ends_with("-# Page 1 / 2 · 12 turns · ~61 tokens"));
+    assert_eq!(
+        second,
+        format!(
+            "**#11** user · ~2 tokens\n> turn 10\n**#12** model · ~39 tokens · pinned\n> {}...\n-# Page 2 / 2 · 12 turns · ~61 tokens",
+            "word ".repeat(20).trim_end()
+        )
+    );
+    assert_eq!(history_page(&[], 1), "The conversation is empty");
+}
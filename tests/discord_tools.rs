@@ -0,0 +1,88 @@
+use rust_discord_bot::discord_tools::{check_access, ChannelAccess};
+use serde_json::json;
+use serenity::all::{GuildChannel, Member, PartialGuild, Permissions};
+
+const VIEW_AND_READ: u64 = 1024 | 65536;
+
+fn guild(everyone: u64) -> PartialGuild {
+    serde_json::from_value(json!({
+        "id": "1", "name": "g", "owner_id": "99", "icon": null, "splash": null,
+        "discovery_splash": null, "verification_level": 0, "default_message_notifications": 0,
+        "explicit_content_filter": 0,
+        "roles": [{
+            "id": "1", "name": "@everyone", "permissions": everyone.to_string(), "position": 0,
+            "color": 0, "hoist": false, "managed": false, "mentionable": false, "flags": 0
+        }],
+        "emojis": [], "features": [], "mfa_level": 0, "system_channel_flags": 0,
+        "premium_tier": 0, "preferred_locale": "en-US", "nsfw_level": 0, "stickers": [],
+        "premium_progress_bar_enabled": false
+    }))
+    .unwrap()
+}
+
+fn member() -> Member {
+    serde_json::from_value(json!({
+        "user": {"id": "42", "username": "u", "discriminator": "0", "global_name": null, "avatar": null},
+        "guild_id": "1", "roles": [], "joined_at": null, "deaf": false, "mute": false, "flags": 0
+    }))
+    .unwrap()
+}
+
+// a text channel that denies the bits in deny to everyone
+fn channel(id: u64, deny: u64) -> GuildChannel {
+    serde_json::from_value(json!({
+        "id": id.to_string(), "guild_id": "1", "type": 0, "name": "general",
+        "permission_overwrites": [{"id": "1", "type": 0, "allow": "0", "deny": deny.to_string()}]
+    }))
+    .unwrap()
+}
+
+fn thread(parent_id: u64) -> GuildChannel {
+    serde_json::from_value(json!({
+        "id": "8", "guild_id": "1", "type": 11, "name": "thread", "parent_id": parent_id.to_string(),
+        "permission_overwrites": [],
+        "thread_metadata": {"archived": false, "auto_archive_duration": 60, "locked": false}
+    }))
+    .unwrap()
+}
+
+fn access(channel: GuildChannel, parent: Option<GuildChannel>) -> ChannelAccess {
+    ChannelAccess {
+        guild: guild(VIEW_AND_READ),
+        member: member(),
+        channel,
+        parent,
+    }
+}
+
+#[test]
+fn visible_channels_are_allowed() {
+    let access = access(channel(7, 0), None);
+    let needed = Permissions::VIEW_CHANNEL | Permissions::READ_MESSAGE_HISTORY;
+    assert_eq!(check_access(Some(&access), needed), Ok(()));
+}
+
+#[test]
+fn hidden_channels_are_denied() {
+    let access = access(channel(7, 1024), None);
+    assert_eq!(
+        check_access(Some(&access), Permissions::VIEW_CHANNEL),
+        Err(String::from("The user is not allowed to see this"))
+    );
+}
+
+#[test]
+fn threads_use_the_permissions_of_their_channel() {
+    let hidden = access(thread(7), Some(channel(7, 1024)));
+    assert!(check_access(Some(&hidden), Permissions::VIEW_CHANNEL).is_err());
+    let visible = access(thread(7), Some(channel(7, 0)));
+    assert_eq!(
+        check_access(Some(&visible), Permissions::VIEW_CHANNEL),
+        Ok(())
+    );
+}
+
+#[test]
+fn direct_messages_are_allowed() {
+    assert_eq!(check_access(None, Permissions::VIEW_CHANNEL), Ok(()));
+}
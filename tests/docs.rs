@@ -0,0 +1,80 @@
+use rust_discord_bot::docs::{chunk_document, cosine_similarity, read_documents, Chunk, DocIndex};
+
+fn chunk(source: &str, embedding: Vec<f32>) -> Chunk {
+    Chunk {
+        source: source.to_string(),
+        embedding,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn documents_are_chunked_by_heading() {
+    let text =
+        "Intro text.\n\n# Install\n\nRun the installer.\n\nThen reboot.\n\n## Usage\n\nType !help.";
+
+    let chunks = chunk_document("guide.md", text, 1500);
+
+    let citations: Vec<String> = chunks.iter().map(|chunk| chunk.citation()).collect();
+    assert_eq!(
+        citations,
+        vec!["guide.md", "guide.md#Install", "guide.md#Usage"]
+    );
+    assert_eq!(
+        chunks[1].text,
+        "# Install\n\nRun the installer.\n\nThen reboot."
+    );
+}
+
+#[test]
+fn long_paragraphs_are_cut() {
+    let text = format!("short\n\n{}", "é".repeat(30));
+
+    let chunks = chunk_document("long.txt", &text, 25);
+
+    assert_eq!(chunks[0].text, "short");
+    assert!(chunks.iter().all(|chunk| chunk.text.len() <= 25));
+    let joined: String = chunks[1..]
+        .iter()
+        .map(|chunk| chunk.text.as_str())
+        .collect();
+    assert_eq!(joined, "é".repeat(30));
+}
+
+#[test]
+fn closest_chunks_are_found() {
+    let mut index = DocIndex::default();
+    index.replace(vec![
+        chunk("a.md", vec![1.0, 0.0]),
+        chunk("b.md", vec![0.7, 0.7]),
+        chunk("c.md", vec![0.0, 1.0]),
+    ]);
+
+    let found: Vec<&str> = index
+        .search(&[1.0, 0.1], 2, 0.5)
+        .iter()
+        .map(|chunk| chunk.source.as_str())
+        .collect();
+
+    assert_eq!(found, vec!["a.md", "b.md"]);
+    assert!(index.search(&[0.0, -1.0], 2, 0.5).is_empty());
+    assert_eq!(cosine_similarity(&[1.0], &[1.0, 0.0]), 0.0);
+}
+
+#[test]
+fn only_text_documents_are_read() {
+    let dir = std::env::temp_dir().join(format!("docs-test-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("guides")).unwrap();
+    std::fs::write(dir.join("readme.md"), "# Readme").unwrap();
+    std::fs::write(dir.join("guides/setup.txt"), "setup").unwrap();
+    std::fs::write(dir.join("logo.png"), "not text").unwrap();
+
+    let documents = read_documents(&dir).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let sources: Vec<&str> = documents
+        .iter()
+        .map(|(source, _)| source.as_str())
+        .collect();
+    assert_eq!(sources, vec!["guides/setup.txt", "readme.md"]);
+}
@@ -0,0 +1,504 @@
+mod common;
+
+use common::MockGemini;
+use std::time::Duration;
+
+use rust_discord_bot::gemini::{Gemini, GeminiError, RetryPolicy};
+use rust_discord_bot::structs::{Conversation, InlineData};
+
+const API_KEY: &str = "test-key";
+const MODEL: &str = "gemini-1.5-flash-001";
+
+async fn setup() -> (MockGemini, Gemini) {
+    let mock = MockGemini::start().await;
+    let mut gemini = Gemini::new(
+        API_KEY.to_string(),
+        "gemini-1.5-flash-001".to_string(),
+        &mock.base_url,
+    );
+    // every canned error is the final answer unless a test retries
+    gemini.retry = RetryPolicy::none();
+    (mock, gemini)
+}
+
+fn retrying(gemini: &mut Gemini, retries: u32) {
+    gemini.retry = RetryPolicy {
+        retries,
+        backoff: Duration::ZERO,
+    };
+}
+
+#[tokio::test]
+async fn success_keeps_both_turns() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::text("Hi there!"));
+
+    let mut conversation = Conversation::default();
+    let (text, usage) = gemini
+        .send_msg(
+            MODEL,
+            &mut conversation,
+            "Hello".to_string(),
+            vec![],
+            vec![],
+        )
+        .await;
+
+    assert_eq!(text, "Hi there!");
+    assert_eq!(usage.totalTokenCount, 15);
+    assert_eq!(conversation.token_count, 15);
+    assert_eq!(conversation.contents.len(), 2);
+    assert_eq!(conversation.contents[0].role, "user");
+    assert_eq!(conversation.contents[0].parts[0].text, "Hello");
+    assert_eq!(conversation.contents[1].role, "model");
+    assert_eq!(conversation.contents[1].parts[0].text, "Hi there!");
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(
+        requests[0].path,
+        "/v1beta/models/gemini-1.5-flash-001:generateContent"
+    );
+    assert_eq!(requests[0].header("x-goog-api-key"), Some(API_KEY));
+}
+
+#[tokio::test]
+async fn prompt_keeps_its_message_ids() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::text("Hi there!"));
+
+    let mut conversation = Conversation::default();
+    gemini
+        .send_msg(
+            MODEL,
+            &mut conversation,
+            "Hello".to_string(),
+            vec![],
+            vec![7],
+        )
+        .await;
+
+    assert_eq!(conversation.find_message(7), Some(0));
+    // the ids are only kept in the history, gemini doesn't see them
+    let body = mock.requests()[0].json();
+    assert!(body["contents"][0].get("message_ids").is_none());
+}
+
+#[tokio::test]
+async fn history_is_sent_with_next_message() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::text("first reply"));
+    mock.push(common::text("second reply"));
+
+    let mut conversation = Conversation::default();
+    gemini
+        .send_msg(
+            MODEL,
+            &mut conversation,
+            "first".to_string(),
+            vec![],
+            vec![],
+        )
+        .await;
+    gemini
+        .send_msg(
+            MODEL,
+            &mut conversation,
+            "second".to_string(),
+            vec![],
+            vec![],
+        )
+        .await;
+
+    let body = mock.requests()[1].json();
+    let contents = body["contents"].as_array().unwrap();
+    assert_eq!(contents.len(), 3);
+    assert_eq!(contents[0]["parts"][0]["text"], "first");
+    assert_eq!(contents[1]["parts"][0]["text"], "first reply");
+    assert_eq!(contents[2]["parts"][0]["text"], "second");
+    assert_eq!(body["safety_settings"].as_array().unwrap().len(), 4);
+}
+
+#[tokio::test]
+async fn image_is_sent_once() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::text("a cat"));
+    mock.push(common::text("yes"));
+
+    let mut conversation = Conversation::default();
+    let image = InlineData {
+        mimeType: "image/png".to_string(),
+        data: "aGVsbG8=".to_string(),
+    };
+    gemini
+        .send_msg(
+            MODEL,
+            &mut conversation,
+            "what is this?".to_string(),
+            vec![image],
+            vec![],
+        )
+        .await;
+    gemini
+        .send_msg(
+            MODEL,
+            &mut conversation,
+            "are you sure?".to_string(),
+            vec![],
+            vec![],
+        )
+        .await;
+
+    let requests = mock.requests();
+    let first = requests[0].json();
+    assert_eq!(
+        first["contents"][0]["parts"][0]["inlineData"]["mimeType"],
+        "image/png"
+    );
+    assert_eq!(first["contents"][0]["parts"][1]["text"], "what is this?");
+    let second = requests[1].json();
+    assert_eq!(second["contents"][0]["parts"].as_array().unwrap().len(), 1);
+    assert_eq!(second["contents"][0]["parts"][0]["text"], "what is this?");
+}
+
+#[tokio::test]
+async fn safety_reverts_user_turn() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::text("ok"));
+    mock.push(common::safety());
+
+    let mut conversation = Conversation::default();
+    gemini
+        .send_msg(MODEL, &mut conversation, "fine".to_string(), vec![], vec![])
+        .await;
+    let (text, usage) = gemini
+        .send_msg(MODEL, &mut conversation, "bad".to_string(), vec![], vec![])
+        .await;
+
+    assert_eq!(text, "https://i.imgur.com/DJqE6wq.jpeg");
+    assert_eq!(usage.totalTokenCount, -1);
+    assert_eq!(conversation.contents.len(), 2);
+    assert_eq!(conversation.contents[1].parts[0].text, "ok");
+}
+
+#[tokio::test]
+async fn max_tokens_keeps_truncated_reply() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::finished("truncated", "MAX_TOKENS"));
+
+    let mut conversation = Conversation::default();
+    let (text, usage) = gemini
+        .send_msg(
+            MODEL,
+            &mut conversation,
+            "long story".to_string(),
+            vec![],
+            vec![],
+        )
+        .await;
+
+    assert_eq!(text, "truncated");
+    assert_eq!(usage.totalTokenCount, 15);
+    assert_eq!(conversation.contents.len(), 2);
+    assert!(conversation.truncated);
+    assert_eq!(conversation.replies, 1);
+}
+
+#[tokio::test]
+async fn rate_limit_shows_error_message() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::error(
+        429,
+        "RESOURCE_EXHAUSTED",
+        "Resource has been exhausted (e.g. check quota).",
+    ));
+
+    let mut conversation = Conversation::default();
+    let (text, usage) = gemini
+        .send_msg(
+            MODEL,
+            &mut conversation,
+            "hello".to_string(),
+            vec![],
+            vec![],
+        )
+        .await;
+
+    assert_eq!(text, "Resource has been exhausted (e.g. check quota).");
+    assert_eq!(usage.totalTokenCount, -1);
+    assert!(conversation.contents.is_empty());
+}
+
+#[tokio::test]
+async fn error_message_hides_api_key() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::error(
+        400,
+        "INVALID_ARGUMENT",
+        &format!("API key {} not valid", API_KEY),
+    ));
+
+    let mut conversation = Conversation::default();
+    let (text, _) = gemini
+        .send_msg(
+            MODEL,
+            &mut conversation,
+            "hello".to_string(),
+            vec![],
+            vec![],
+        )
+        .await;
+
+    assert_eq!(text, "API key API KEY not valid");
+}
+
+#[tokio::test]
+async fn malformed_json_reverts_user_turn() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::raw(200, "{\"candidates\": [{\"content\": "));
+
+    let mut conversation = Conversation::default();
+    let (text, usage) = gemini
+        .send_msg(
+            MODEL,
+            &mut conversation,
+            "hello".to_string(),
+            vec![],
+            vec![],
+        )
+        .await;
+
+    assert_eq!(text, "Error deserializing json received from gemini");
+    assert_eq!(usage.totalTokenCount, -1);
+    assert!(conversation.contents.is_empty());
+}
+
+#[tokio::test]
+async fn empty_candidates_reverts_user_turn() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::raw(200, "{\"candidates\": []}"));
+
+    let mut conversation = Conversation::default();
+    let (text, usage) = gemini
+        .send_msg(
+            MODEL,
+            &mut conversation,
+            "hello".to_string(),
+            vec![],
+            vec![],
+        )
+        .await;
+
+    assert_eq!(text, "Unknown error");
+    assert_eq!(usage.totalTokenCount, -1);
+    assert!(conversation.contents.is_empty());
+}
+
+#[tokio::test]
+async fn unreachable_server_reverts_user_turn() {
+    let mut gemini = Gemini::new(
+        API_KEY.to_string(),
+        "gemini-1.5-flash-001".to_string(),
+        "http://127.0.0.1:1/v1beta",
+    );
+    gemini.retry = RetryPolicy::none();
+
+    let mut conversation = Conversation::default();
+    let (text, _) = gemini
+        .send_msg(
+            MODEL,
+            &mut conversation,
+            "hello".to_string(),
+            vec![],
+            vec![],
+        )
+        .await;
+
+    assert_eq!(text, "Error sending POST request to gemini");
+    assert!(conversation.contents.is_empty());
+}
+
+#[tokio::test]
+async fn old_turns_are_deleted() {
+    let (mock, gemini) = setup().await;
+    let mut conversation = Conversation::default();
+    for i in 0..15 {
+        mock.push(common::text(&format!("reply {}", i)));
+        gemini
+            .send_msg(
+                MODEL,
+                &mut conversation,
+                format!("message {}", i),
+                vec![],
+                vec![],
+            )
+            .await;
+    }
+
+    assert!(conversation.contents.len() < 20);
+    let last = conversation.contents.last().unwrap();
+    assert_eq!(last.parts[0].text, "reply 14");
+}
+
+#[tokio::test]
+async fn rate_limit_is_retried() {
+    let (mock, mut gemini) = setup().await;
+    retrying(&mut gemini, 2);
+    mock.push(common::error(429, "RESOURCE_EXHAUSTED", "slow down"));
+    mock.push(common::error(503, "UNAVAILABLE", "overloaded"));
+    mock.push(common::text("finally"));
+
+    let mut conversation = Conversation::default();
+    let (text, _) = gemini
+        .send_msg(
+            MODEL,
+            &mut conversation,
+            "hello".to_string(),
+            vec![],
+            vec![],
+        )
+        .await;
+
+    assert_eq!(text, "finally");
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[0].body, requests[2].body);
+}
+
+#[tokio::test]
+async fn retries_are_limited() {
+    let (mock, mut gemini) = setup().await;
+    retrying(&mut gemini, 1);
+    mock.push(common::error(500, "INTERNAL", "first"));
+    mock.push(common::error(500, "INTERNAL", "second"));
+
+    let result = gemini.one_shot(MODEL, "be brief", "hello").await;
+
+    assert_eq!(
+        result.unwrap_err(),
+        GeminiError::Api(String::from("second"))
+    );
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn client_errors_are_not_retried() {
+    let (mock, mut gemini) = setup().await;
+    retrying(&mut gemini, 2);
+    mock.push(common::error(400, "INVALID_ARGUMENT", "bad request"));
+
+    let result = gemini.one_shot(MODEL, "be brief", "hello").await;
+
+    assert_eq!(
+        result.unwrap_err(),
+        GeminiError::Api(String::from("bad request"))
+    );
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn one_shot_sends_only_the_prompt() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::text("short"));
+    mock.push(common::safety());
+
+    let (text, usage) = gemini.one_shot(MODEL, "be brief", "hello").await.unwrap();
+
+    assert_eq!(text, "short");
+    assert_eq!(usage.totalTokenCount, 15);
+    let body = mock.requests()[0].json();
+    assert_eq!(body["systemInstruction"]["parts"][0]["text"], "be brief");
+    assert_eq!(body["contents"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        gemini
+            .one_shot(MODEL, "be brief", "hello")
+            .await
+            .unwrap_err(),
+        GeminiError::Safety
+    );
+}
+
+#[derive(Debug, serde::Deserialize, PartialEq)]
+struct Poll {
+    question: String,
+    options: Vec<String>,
+}
+
+fn poll_schema() -> serde_json::Value {
+    serde_json::json!({
+        "type": "OBJECT",
+        "properties": {
+            "question": {"type": "STRING"},
+            "options": {"type": "ARRAY", "items": {"type": "STRING"}}
+        }
+    })
+}
+
+#[tokio::test]
+async fn json_output_is_deserialized() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::text(
+        "{\"question\": \"Pizza?\", \"options\": [\"yes\", \"no\"]}",
+    ));
+
+    let (poll, usage): (Poll, _) = gemini
+        .one_shot_json(MODEL, "make a poll", "pizza or not", poll_schema())
+        .await
+        .unwrap();
+
+    assert_eq!(poll.question, "Pizza?");
+    assert_eq!(poll.options, vec!["yes", "no"]);
+    assert_eq!(usage.totalTokenCount, 15);
+    let body = mock.requests()[0].json();
+    assert_eq!(
+        body["generationConfig"]["responseMimeType"],
+        "application/json"
+    );
+    assert_eq!(body["generationConfig"]["responseSchema"], poll_schema());
+}
+
+#[tokio::test]
+async fn invalid_json_is_asked_again_once() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::text("{\"question\": \"Pizza?\"}"));
+    mock.push(common::text(
+        "{\"question\": \"Pizza?\", \"options\": [\"yes\"]}",
+    ));
+
+    let (poll, usage): (Poll, _) = gemini
+        .one_shot_json(MODEL, "make a poll", "pizza or not", poll_schema())
+        .await
+        .unwrap();
+
+    assert_eq!(poll.options, vec!["yes"]);
+    assert_eq!(usage.promptTokenCount, 20);
+    let contents = mock.requests()[1].json()["contents"].clone();
+    assert_eq!(contents.as_array().unwrap().len(), 3);
+    assert_eq!(
+        contents[1]["parts"][0]["text"],
+        "{\"question\": \"Pizza?\"}"
+    );
+    assert!(contents[2]["parts"][0]["text"]
+        .as_str()
+        .unwrap()
+        .starts_with("That is not valid JSON for the schema: missing field `options`"));
+}
+
+#[tokio::test]
+async fn json_output_gives_up_after_asking_again() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::text("not json"));
+    mock.push(common::text("still not json"));
+
+    let error = gemini
+        .one_shot_json::<Poll>(MODEL, "make a poll", "pizza or not", poll_schema())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(error, GeminiError::InvalidJson(_)));
+    assert!(error
+        .to_string()
+        .starts_with("Gemini answered with invalid JSON"));
+    assert_eq!(mock.requests().len(), 2);
+}
@@ -0,0 +1,79 @@
+use rust_discord_bot::generation::parse_flags;
+use rust_discord_bot::structs::GenerationConfig;
+
+#[test]
+fn flags_are_taken_out_of_the_text() {
+    let (text, config) =
+        parse_flags("write a poem --temp 0.2 about cats --max-tokens 100 --stop END").unwrap();
+
+    assert_eq!(text, "write a poem about cats");
+    assert_eq!(config.temperature, Some(0.2));
+    assert_eq!(config.maxOutputTokens, Some(100));
+    assert_eq!(config.stopSequences, vec![String::from("END")]);
+    assert_eq!(config.topP, None);
+}
+
+#[test]
+fn unknown_flags_stay_in_the_text() {
+    let (text, config) = parse_flags("what does --verbose do").unwrap();
+
+    assert_eq!(text, "what does --verbose do");
+    assert!(config.is_empty());
+}
+
+#[test]
+fn invalid_values_are_errors() {
+    assert_eq!(
+        parse_flags("hi --temp hot").unwrap_err(),
+        "Invalid value for --temp: hot"
+    );
+    assert_eq!(
+        parse_flags("hi --top-k").unwrap_err(),
+        "Missing value for --top-k"
+    );
+}
+
+#[test]
+fn later_levels_override_earlier_ones() {
+    let guild = GenerationConfig {
+        temperature: Some(1.0),
+        topK: Some(40),
+        ..Default::default()
+    };
+    let channel = GenerationConfig {
+        temperature: Some(0.5),
+        ..Default::default()
+    };
+
+    let merged = guild.merge(&channel);
+
+    assert_eq!(merged.temperature, Some(0.5));
+    assert_eq!(merged.topK, Some(40));
+}
+
+#[test]
+fn values_are_checked_against_model_limits() {
+    let too_hot = GenerationConfig {
+        temperature: Some(2.5),
+        ..Default::default()
+    };
+    let too_long = GenerationConfig {
+        maxOutputTokens: Some(10000),
+        ..Default::default()
+    };
+    let fine = GenerationConfig {
+        temperature: Some(0.7),
+        topP: Some(0.9),
+        topK: Some(20),
+        maxOutputTokens: Some(8192),
+        stopSequences: vec![String::from("END")],
+        ..Default::default()
+    };
+
+    assert!(too_hot.validate("gemini-1.5-flash-001").is_err());
+    assert_eq!(
+        too_long.validate("gemini-1.5-flash-001").unwrap_err(),
+        "Max tokens must be between 1 and 8192 for gemini-1.5-flash-001, got 10000"
+    );
+    assert!(fine.validate("gemini-1.5-flash-001").is_ok());
+}
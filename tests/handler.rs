@@ -0,0 +1,2146 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::MockGemini;
+use rust_discord_bot::bot::{
+    Attachment, Bot, Button, IncomingMessage, QuotedMessage, Transport, OWNER_ID,
+};
+use rust_discord_bot::config::{Config, DmMode, PresenceMode};
+use rust_discord_bot::gemini::Gemini;
+use rust_discord_bot::moderation::RateLimiter;
+use rust_discord_bot::persona::Personas;
+use rust_discord_bot::summarize::HistoryMessage;
+use rust_discord_bot::usage::MODERATION_USER;
+use serenity::async_trait;
+
+const BOT_ID: u64 = 1000;
+const USER_ID: u64 = 42;
+const CHANNEL_ID: u64 = 7;
+const PROMPT_ID: u64 = 300;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Event {
+    Reply(String),
+    Say(String),
+    Typing,
+    Presence(String),
+    Thread(String),
+    // labels of the buttons under the previous reply
+    Buttons(Vec<String>),
+    Deleted,
+    // a message deleted by its id
+    DeletedId(u64),
+    Edit(u64, String),
+    SendTo(u64, String),
+    // name and content of a file the bot sent
+    File(String, String),
+}
+
+#[derive(Default)]
+struct FakeTransport {
+    // shared with the transports of threads it creates
+    events: Arc<Mutex<Vec<Event>>>,
+    // returned by download, an error if None
+    file: Option<Vec<u8>>,
+    // id of the thread create_thread starts, an error if None
+    thread: Option<u64>,
+    // the channel's messages, oldest first
+    history: Vec<HistoryMessage>,
+    // the channel's pinned messages
+    pins: Vec<HistoryMessage>,
+    // returned by reply_chain
+    chain: Vec<QuotedMessage>,
+}
+
+impl FakeTransport {
+    fn events(&self) -> Vec<Event> {
+        self.events.lock().unwrap().clone()
+    }
+
+    fn replies(&self) -> Vec<String> {
+        self.events()
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Reply(text) => Some(text),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Transport for FakeTransport {
+    // replies are numbered by their position in the events
+    async fn reply(&self, text: &str) -> Option<u64> {
+        let mut events = self.events.lock().unwrap();
+        events.push(Event::Reply(text.to_string()));
+        Some(events.len() as u64)
+    }
+
+    async fn say(&self, text: &str) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(Event::Say(text.to_string()));
+    }
+
+    async fn typing(&self) {
+        self.events.lock().unwrap().push(Event::Typing);
+    }
+
+    async fn set_presence(&self, status: &str) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(Event::Presence(status.to_string()));
+    }
+
+    async fn download(&self, _attachment: &Attachment) -> Result<Vec<u8>, String> {
+        self.file
+            .clone()
+            .ok_or_else(|| String::from("download failed"))
+    }
+
+    async fn create_thread(&self, name: &str) -> Result<(u64, Box<dyn Transport>), String> {
+        let thread_id = self.thread.ok_or_else(|| String::from("no permission"))?;
+        self.events
+            .lock()
+            .unwrap()
+            .push(Event::Thread(name.to_string()));
+        let transport = FakeTransport {
+            events: self.events.clone(),
+            ..Default::default()
+        };
+        Ok((thread_id, Box::new(transport)))
+    }
+
+    async fn reply_with_buttons(&self, text: &str, buttons: &[Button]) -> Option<u64> {
+        let id = self.reply(text).await;
+        self.events.lock().unwrap().push(Event::Buttons(
+            buttons.iter().map(|b| b.custom_id()).collect(),
+        ));
+        id
+    }
+
+    async fn edit(&self, message_id: u64, text: &str, _buttons: &[Button]) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(Event::Edit(message_id, text.to_string()));
+    }
+
+    async fn history(
+        &self,
+        limit: usize,
+        _since: Option<i64>,
+    ) -> Result<Vec<HistoryMessage>, String> {
+        let skip = self.history.len().saturating_sub(limit);
+        Ok(self.history[skip..].to_vec())
+    }
+
+    async fn delete_message(&self) {
+        self.events.lock().unwrap().push(Event::Deleted);
+    }
+
+    async fn delete(&self, message_id: u64) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(Event::DeletedId(message_id));
+    }
+
+    async fn send_to(&self, channel_id: u64, text: &str) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(Event::SendTo(channel_id, text.to_string()));
+    }
+
+    async fn pins(&self) -> Result<Vec<HistoryMessage>, String> {
+        Ok(self.pins.clone())
+    }
+
+    async fn reply_chain(&self, depth: usize) -> Vec<QuotedMessage> {
+        self.chain.iter().take(depth).cloned().collect()
+    }
+
+    async fn send_file(&self, _text: &str, filename: &str, content: Vec<u8>) -> Result<(), String> {
+        self.events.lock().unwrap().push(Event::File(
+            filename.to_string(),
+            String::from_utf8(content).unwrap(),
+        ));
+        Ok(())
+    }
+}
+
+async fn setup() -> (MockGemini, Bot) {
+    let mock = MockGemini::start().await;
+    let config = Config {
+        base_url: mock.base_url.clone(),
+        presence: PresenceMode::Static(String::from("Chatting")),
+        usage_file: String::new(),
+        settings_file: String::new(),
+        memory_file: String::new(),
+        docs_index_file: String::new(),
+        kb_file: String::new(),
+        ..Default::default()
+    };
+    let gemini = Gemini::new(
+        String::from("test-key"),
+        config.model.clone(),
+        &config.base_url,
+    );
+    let bot = Bot::new(gemini, Personas::default(), config);
+    (mock, bot)
+}
+
+fn message(content: &str) -> IncomingMessage {
+    IncomingMessage {
+        message_id: PROMPT_ID,
+        author_id: USER_ID,
+        channel_id: CHANNEL_ID,
+        guild_id: Some(1),
+        content: content.to_string(),
+        bot_id: BOT_ID,
+        mentions_bot: content.contains(&format!("<@{}>", BOT_ID)),
+        attachments: Vec::new(),
+        referenced: Vec::new(),
+        local: false,
+    }
+}
+
+fn image(content_type: Option<&str>) -> Attachment {
+    Attachment {
+        url: String::from("https://cdn.example/cat.png"),
+        filename: String::from("cat.png"),
+        content_type: content_type.map(str::to_string),
+    }
+}
+
+#[tokio::test]
+async fn ignores_untriggered_messages() {
+    let (mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("just chatting"))
+        .await;
+
+    assert!(transport.events().is_empty());
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn ignores_own_messages() {
+    let (mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+    let msg = IncomingMessage {
+        author_id: BOT_ID,
+        ..message("? hello")
+    };
+
+    bot.handle_message(&transport, &msg).await;
+
+    assert!(transport.events().is_empty());
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn mention_is_answered() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("Hello!"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message(&format!("<@{}> hi", BOT_ID)))
+        .await;
+
+    assert_eq!(
+        transport.events(),
+        vec![
+            Event::Typing,
+            Event::Reply(String::from(
+                "Hello!\n-# gemini-1.5-flash-001 · context 15 / 1M tokens"
+            )),
+            Event::Buttons(vec![
+                String::from("gemini:regenerate:1:42"),
+                String::from("gemini:delete:1:42"),
+            ]),
+            Event::Presence(String::from("Chatting")),
+        ]
+    );
+    let body = mock.requests()[0].json();
+    assert_eq!(body["contents"][0]["parts"][0]["text"], "hi");
+}
+
+#[tokio::test]
+async fn question_mark_is_answered() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("42"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? meaning of life"))
+        .await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![String::from(
+            "42\n-# gemini-1.5-flash-001 · context 15 / 1M tokens"
+        )]
+    );
+    let body = mock.requests()[0].json();
+    assert_eq!(body["contents"][0]["parts"][0]["text"], "meaning of life");
+}
+
+#[tokio::test]
+async fn failed_request_has_no_footer() {
+    let (mock, bot) = setup().await;
+    mock.push(common::safety());
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? something bad"))
+        .await;
+
+    assert_eq!(
+        transport.events(),
+        vec![
+            Event::Typing,
+            Event::Reply(String::from("https://i.imgur.com/DJqE6wq.jpeg")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn long_replies_are_split() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text(&"a".repeat(2500)));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? write a lot"))
+        .await;
+
+    let replies = transport.replies();
+    assert_eq!(replies.len(), 2);
+    assert_eq!(replies[0], "a".repeat(2000));
+    assert_eq!(
+        replies[1],
+        format!(
+            "{}\n-# gemini-1.5-flash-001 · context 15 / 1M tokens",
+            "a".repeat(500)
+        )
+    );
+}
+
+#[tokio::test]
+async fn channels_have_separate_history() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("one"));
+    mock.push(common::text("two"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? first")).await;
+    let other_channel = IncomingMessage {
+        channel_id: CHANNEL_ID + 1,
+        ..message("? second")
+    };
+    bot.handle_message(&transport, &other_channel).await;
+
+    let body = mock.requests()[1].json();
+    assert_eq!(body["contents"].as_array().unwrap().len(), 1);
+    assert_eq!(body["contents"][0]["parts"][0]["text"], "second");
+}
+
+#[tokio::test]
+async fn owner_can_reset() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("remembered"));
+    let transport = FakeTransport::default();
+    bot.handle_message(&transport, &message("? remember this"))
+        .await;
+
+    let reset = FakeTransport::default();
+    let msg = IncomingMessage {
+        author_id: OWNER_ID,
+        ..message("!resetgemini")
+    };
+    bot.handle_message(&reset, &msg).await;
+
+    assert_eq!(
+        reset.events(),
+        vec![
+            Event::Typing,
+            Event::Say(String::from("Conversation has been reset!"))
+        ]
+    );
+    let conversation = bot.get_conversation(CHANNEL_ID).await;
+    assert!(conversation.lock().await.contents.is_empty());
+}
+
+#[tokio::test]
+async fn others_cannot_reset() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("remembered"));
+    let transport = FakeTransport::default();
+    bot.handle_message(&transport, &message("? remember this"))
+        .await;
+
+    let reset = FakeTransport::default();
+    bot.handle_message(&reset, &message("!resetgemini")).await;
+
+    assert!(reset.events().is_empty());
+    let conversation = bot.get_conversation(CHANNEL_ID).await;
+    assert_eq!(conversation.lock().await.contents.len(), 2);
+}
+
+#[tokio::test]
+async fn image_attachment_is_sent() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("a cat"));
+    let transport = FakeTransport {
+        file: Some(b"hello".to_vec()),
+        ..Default::default()
+    };
+    let msg = IncomingMessage {
+        attachments: vec![image(Some("image/png"))],
+        ..message("? what is this")
+    };
+
+    bot.handle_message(&transport, &msg).await;
+
+    let body = mock.requests()[0].json();
+    assert_eq!(
+        body["contents"][0]["parts"][0]["inlineData"]["data"],
+        "aGVsbG8="
+    );
+    assert_eq!(
+        transport.replies(),
+        vec![String::from(
+            "a cat\n-# gemini-1.5-flash-001 · context 15 / 1M tokens"
+        )]
+    );
+}
+
+#[tokio::test]
+async fn unsupported_attachment_is_rejected() {
+    let (mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+    let msg = IncomingMessage {
+        attachments: vec![image(Some("application/zip"))],
+        ..message("? what is this")
+    };
+
+    bot.handle_message(&transport, &msg).await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![String::from("Unsupported attachment type")]
+    );
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn attachment_without_content_type_is_rejected() {
+    let (mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+    let msg = IncomingMessage {
+        attachments: vec![image(None)],
+        ..message("? what is this")
+    };
+
+    bot.handle_message(&transport, &msg).await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![String::from("Could not find content type of attachment")]
+    );
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn failed_download_is_reported() {
+    let (mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+    let msg = IncomingMessage {
+        attachments: vec![image(Some("image/png"))],
+        ..message("? what is this")
+    };
+
+    bot.handle_message(&transport, &msg).await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![String::from("Error downloading attachment")]
+    );
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn usage_is_reported() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("hi"));
+    let transport = FakeTransport::default();
+    bot.handle_message(&transport, &message("? hello")).await;
+
+    let report = FakeTransport::default();
+    bot.handle_message(&report, &message("!usage")).await;
+
+    let replies = report.replies();
+    assert_eq!(replies.len(), 1);
+    assert!(replies[0].contains("1 requests, 10 prompt + 5 candidate = 15 tokens"));
+}
+
+fn admin(content: &str) -> IncomingMessage {
+    IncomingMessage {
+        author_id: OWNER_ID,
+        ..message(content)
+    }
+}
+
+#[tokio::test]
+async fn models_are_listed() {
+    let (mock, bot) = setup().await;
+    mock.push(common::models(&[
+        "gemini-1.5-flash-001",
+        "gemini-1.5-pro-001",
+    ]));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("!models")).await;
+
+    let replies = transport.replies();
+    assert!(replies[0].contains("`gemini-1.5-pro-001`"));
+    assert_eq!(mock.requests()[0].method, "GET");
+    assert_eq!(mock.requests()[0].path, "/v1beta/models?pageSize=1000");
+}
+
+#[tokio::test]
+async fn admin_switches_channel_model() {
+    let (mock, bot) = setup().await;
+    mock.push(common::models(&[
+        "gemini-1.5-flash-001",
+        "gemini-1.5-pro-001",
+    ]));
+    mock.push(common::text("from pro"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &admin("!model gemini-1.5-pro-001"))
+        .await;
+    bot.handle_message(&transport, &message("? hello")).await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![
+            String::from("Switched to gemini-1.5-pro-001"),
+            String::from("from pro\n-# gemini-1.5-pro-001 · context 15 / 2.1M tokens"),
+        ]
+    );
+    assert_eq!(
+        mock.requests()[1].path,
+        "/v1beta/models/gemini-1.5-pro-001:generateContent"
+    );
+}
+
+#[tokio::test]
+async fn unknown_model_is_rejected() {
+    let (mock, bot) = setup().await;
+    mock.push(common::models(&["gemini-1.5-flash-001"]));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &admin("!model gemini-9"))
+        .await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![String::from("Unknown model: gemini-9, see !models")]
+    );
+    assert_eq!(bot.settings.lock().await.channel(CHANNEL_ID).model, None);
+}
+
+#[tokio::test]
+async fn only_admins_switch_models() {
+    let (mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("!model gemini-1.5-pro-001"))
+        .await;
+    bot.handle_message(&transport, &message("!model")).await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![
+            String::from("Only admins can change the model"),
+            String::from("This channel uses the default model (gemini-1.5-flash-001)"),
+        ]
+    );
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn images_are_routed_to_multimodal_model() {
+    let (mock, mut bot) = setup().await;
+    bot.config.route_multimodal_model = Some(String::from("gemini-1.5-pro-001"));
+    mock.push(common::text("a cat"));
+    let transport = FakeTransport {
+        file: Some(b"hello".to_vec()),
+        ..Default::default()
+    };
+    let msg = IncomingMessage {
+        attachments: vec![image(Some("image/png"))],
+        ..message("? what is this")
+    };
+
+    bot.handle_message(&transport, &msg).await;
+
+    assert_eq!(
+        mock.requests()[0].path,
+        "/v1beta/models/gemini-1.5-pro-001:generateContent"
+    );
+    assert!(transport.replies()[0].contains("-# gemini-1.5-pro-001 ·"));
+}
+
+#[tokio::test]
+async fn inline_flags_set_generation_config() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("short"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(
+        &transport,
+        &message("? tell a story --temp 0.2 --max-tokens 50"),
+    )
+    .await;
+
+    let body = mock.requests()[0].json();
+    assert_eq!(body["contents"][0]["parts"][0]["text"], "tell a story");
+    assert_eq!(body["generationConfig"]["temperature"], 0.2);
+    assert_eq!(body["generationConfig"]["maxOutputTokens"], 50);
+}
+
+#[tokio::test]
+async fn invalid_generation_config_is_not_sent() {
+    let (mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? hi --temp 3"))
+        .await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![String::from("Temperature must be between 0 and 2, got 3")]
+    );
+    assert!(mock.requests().is_empty());
+    let conversation = bot.get_conversation(CHANNEL_ID).await;
+    assert!(conversation.lock().await.contents.is_empty());
+}
+
+#[tokio::test]
+async fn channel_generation_settings_apply() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("ok"));
+    mock.push(common::text("ok"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &admin("!gen guild --top-k 10"))
+        .await;
+    bot.handle_message(&transport, &admin("!gen channel --temp 0.5"))
+        .await;
+    bot.handle_message(&transport, &message("? hi")).await;
+    bot.handle_message(&transport, &message("? again --temp 1"))
+        .await;
+
+    let replies = transport.replies();
+    assert_eq!(replies[0], "Generation settings for this guild: top-k 10");
+    assert_eq!(replies[1], "Generation settings for this channel: temp 0.5");
+    let first = mock.requests()[0].json();
+    assert_eq!(first["generationConfig"]["temperature"], 0.5);
+    assert_eq!(first["generationConfig"]["topK"], 10);
+    let second = mock.requests()[1].json();
+    assert_eq!(second["generationConfig"]["temperature"], 1.0);
+}
+
+#[tokio::test]
+async fn no_generation_config_by_default() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("ok"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? hi")).await;
+
+    assert!(mock.requests()[0].json().get("generationConfig").is_none());
+}
+
+fn quoted(author_id: u64, author_name: &str, content: &str) -> QuotedMessage {
+    QuotedMessage {
+        message_id: 0,
+        author_id,
+        author_name: author_name.to_string(),
+        content: content.to_string(),
+        attachments: Vec::new(),
+    }
+}
+
+#[tokio::test]
+async fn replied_message_is_quoted() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("It's a joke"));
+    let transport = FakeTransport::default();
+    let msg = IncomingMessage {
+        referenced: vec![
+            quoted(5, "Bob", "because it's a bit\nof a stretch"),
+            quoted(6, "Alice", "why did the rubber band break up?"),
+        ],
+        ..message(&format!("<@{}> what does this mean?", BOT_ID))
+    };
+
+    bot.handle_message(&transport, &msg).await;
+
+    let body = mock.requests()[0].json();
+    assert_eq!(
+        body["contents"][0]["parts"][0]["text"],
+        "Alice wrote:\n> why did the rubber band break up?\nBob wrote:\n> because it's a bit\n> of a stretch\n\nwhat does this mean?"
+    );
+}
+
+#[tokio::test]
+async fn reply_chain_is_fetched_when_answering() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("It's a joke"));
+    let transport = FakeTransport {
+        chain: vec![
+            quoted(5, "Bob", "because it's a bit of a stretch"),
+            quoted(6, "Alice", "why did the rubber band break up?"),
+        ],
+        ..Default::default()
+    };
+    // discord only includes the message it replies to
+    let msg = IncomingMessage {
+        referenced: vec![quoted(5, "Bob", "because it's a bit of a stretch")],
+        ..message("? what does this mean")
+    };
+
+    bot.handle_message(&transport, &msg).await;
+
+    let body = mock.requests()[0].json();
+    assert_eq!(
+        body["contents"][0]["parts"][0]["text"],
+        "Alice wrote:\n> why did the rubber band break up?\nBob wrote:\n> because it's a bit of a stretch\n\nwhat does this mean"
+    );
+}
+
+#[tokio::test]
+async fn replies_to_the_bot_are_not_quoted() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("earlier answer"));
+    mock.push(common::text("sure"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? a question"))
+        .await;
+    let reply = QuotedMessage {
+        message_id: transport.events().len() as u64 - 2,
+        ..quoted(BOT_ID, "Bot", "earlier answer")
+    };
+    let msg = IncomingMessage {
+        message_id: PROMPT_ID + 1,
+        mentions_bot: true,
+        referenced: vec![reply],
+        ..message("tell me more")
+    };
+    bot.handle_message(&transport, &msg).await;
+
+    let body = mock.requests()[1].json();
+    assert_eq!(body["contents"][2]["parts"][0]["text"], "tell me more");
+}
+
+#[tokio::test]
+async fn bot_messages_outside_the_history_are_quoted() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("it is a summary"));
+    let transport = FakeTransport::default();
+    // a summary is never added to the history
+    let msg = IncomingMessage {
+        mentions_bot: true,
+        referenced: vec![QuotedMessage {
+            message_id: 55,
+            ..quoted(BOT_ID, "Bot", "**Summary**")
+        }],
+        ..message("what does this mean?")
+    };
+
+    bot.handle_message(&transport, &msg).await;
+
+    let body = mock.requests()[0].json();
+    assert_eq!(
+        body["contents"][0]["parts"][0]["text"],
+        "Bot wrote:\n> **Summary**\n\nwhat does this mean?"
+    );
+}
+
+#[tokio::test]
+async fn replied_images_are_sent() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("a cat"));
+    let transport = FakeTransport {
+        file: Some(b"hello".to_vec()),
+        ..Default::default()
+    };
+    let mut referenced = quoted(5, "Bob", "look");
+    referenced.attachments = vec![image(Some("image/png"))];
+    let msg = IncomingMessage {
+        referenced: vec![referenced],
+        ..message("? what is in this picture")
+    };
+
+    bot.handle_message(&transport, &msg).await;
+
+    let body = mock.requests()[0].json();
+    let parts = body["contents"][0]["parts"].as_array().unwrap();
+    assert_eq!(parts[0]["inlineData"]["data"], "aGVsbG8=");
+    assert_eq!(
+        parts[1]["text"],
+        "Bob wrote:\n> look\n\nwhat is in this picture"
+    );
+    assert!(mock.requests()[0].path.ends_with(":generateContent"));
+}
+
+const THREAD_ID: u64 = 500;
+
+#[tokio::test]
+async fn mention_in_thread_channel_starts_thread() {
+    let (mock, mut bot) = setup().await;
+    bot.config.thread_channels = vec![CHANNEL_ID];
+    mock.push(common::text("hello there"));
+    mock.push(common::text("still here"));
+    let transport = FakeTransport {
+        thread: Some(THREAD_ID),
+        ..Default::default()
+    };
+
+    bot.handle_message(&transport, &message(&format!("<@{}> how are you?", BOT_ID)))
+        .await;
+    let in_thread = IncomingMessage {
+        channel_id: THREAD_ID,
+        ..message("and now?")
+    };
+    bot.handle_message(&transport, &in_thread).await;
+
+    let events = transport.events();
+    assert_eq!(events[0], Event::Thread(String::from("how are you?")));
+    assert!(transport.replies()[1].starts_with("still here"));
+    // the thread has its own history, the channel's is untouched
+    let body = mock.requests()[1].json();
+    assert_eq!(body["contents"].as_array().unwrap().len(), 3);
+    let channel = bot.get_conversation(CHANNEL_ID).await;
+    assert!(channel.lock().await.contents.is_empty());
+}
+
+#[tokio::test]
+async fn failed_thread_answers_in_channel() {
+    let (mock, mut bot) = setup().await;
+    bot.config.thread_channels = vec![CHANNEL_ID];
+    mock.push(common::text("hello there"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? how are you?"))
+        .await;
+
+    assert!(transport.replies()[0].starts_with("hello there"));
+    assert!(!transport
+        .events()
+        .iter()
+        .any(|event| matches!(event, Event::Thread(_))));
+}
+
+#[tokio::test]
+async fn inactive_threads_expire() {
+    let (mock, mut bot) = setup().await;
+    bot.config.thread_channels = vec![CHANNEL_ID];
+    mock.push(common::text("hello there"));
+    let transport = FakeTransport {
+        thread: Some(THREAD_ID),
+        ..Default::default()
+    };
+    bot.handle_message(&transport, &message("? how are you?"))
+        .await;
+
+    let now = chrono::Utc::now().timestamp();
+    assert!(bot.expire_threads(now).await.is_empty());
+    assert_eq!(bot.expire_threads(now + 61 * 60).await, vec![THREAD_ID]);
+
+    // expired threads are no longer answered without a mention
+    let in_thread = IncomingMessage {
+        channel_id: THREAD_ID,
+        ..message("anyone?")
+    };
+    bot.handle_message(&transport, &in_thread).await;
+    assert_eq!(mock.requests().len(), 1);
+}
+
+fn dm(content: &str) -> IncomingMessage {
+    IncomingMessage {
+        channel_id: 900,
+        guild_id: None,
+        ..message(content)
+    }
+}
+
+#[tokio::test]
+async fn direct_messages_need_no_trigger() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("hi"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &dm("hello")).await;
+
+    assert_eq!(
+        mock.requests()[0].json()["contents"][0]["parts"][0]["text"],
+        "hello"
+    );
+    assert!(transport.replies()[0].starts_with("hi"));
+}
+
+#[tokio::test]
+async fn opt_in_direct_messages() {
+    let (mock, mut bot) = setup().await;
+    bot.config.dm_mode = DmMode::OptIn;
+    mock.push(common::text("hi"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &dm("hello")).await;
+    bot.handle_message(&transport, &dm("!dm optin")).await;
+    bot.handle_message(&transport, &dm("hello")).await;
+
+    let replies = transport.replies();
+    assert_eq!(
+        replies[0],
+        "Send !dm optin to chat with me in direct messages"
+    );
+    assert_eq!(replies[1], "You can now chat with me in direct messages");
+    assert!(replies[2].starts_with("hi"));
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn allowlist_is_managed_by_admins() {
+    let (mock, mut bot) = setup().await;
+    bot.config.dm_mode = DmMode::Allowlist;
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &dm("!dm optin")).await;
+    bot.handle_message(&transport, &message(&format!("!dm allow {}", USER_ID)))
+        .await;
+    bot.handle_message(&transport, &admin(&format!("!dm allow <@{}>", USER_ID)))
+        .await;
+    bot.handle_message(&transport, &dm("!dm")).await;
+
+    let replies = transport.replies();
+    assert_eq!(replies[0], "Direct messages are not opt-in");
+    assert_eq!(replies[1], "Only admins can allow users");
+    assert_eq!(replies[2], "<@42> can now chat with me in direct messages");
+    assert_eq!(replies[3], "You can chat with me in direct messages");
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn commands_in_direct_messages_are_limited_too() {
+    let (mock, mut bot) = setup().await;
+    bot.config.dm_mode = DmMode::Allowlist;
+    let transport = FakeTransport {
+        history: history(2),
+        ..Default::default()
+    };
+
+    bot.handle_message(&transport, &dm("!translate French hello"))
+        .await;
+    bot.handle_message(&transport, &dm("!summarize")).await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![
+            String::from("Direct messages are limited to allowed users, ask an admin"),
+            String::from("Direct messages are limited to allowed users, ask an admin"),
+        ]
+    );
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn buttons_in_direct_messages_are_limited_too() {
+    let (mock, mut bot) = setup().await;
+    bot.config.dm_mode = DmMode::Open;
+    bot.config.dm_daily_tokens = 10;
+    mock.push(common::text("first take"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &dm("a joke")).await;
+    let press = dm("");
+    bot.handle_button(&transport, &press, "gemini:regenerate:1:42")
+        .await;
+    bot.handle_button(&transport, &press, "gemini:continue:1:42")
+        .await;
+
+    let replies = transport.replies();
+    assert_eq!(
+        replies[1..],
+        [
+            String::from("You have used your daily quota of 10 tokens in direct messages"),
+            String::from("You have used your daily quota of 10 tokens in direct messages"),
+        ]
+    );
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn terminal_messages_skip_direct_message_limits() {
+    let (mock, mut bot) = setup().await;
+    bot.config.dm_mode = DmMode::Allowlist;
+    bot.config.admin_ids = vec![OWNER_ID + 1];
+    bot.config.dm_daily_tokens = 1;
+    mock.push(common::text("hi"));
+    mock.push(common::text("again"));
+    let transport = FakeTransport::default();
+    let terminal = IncomingMessage {
+        author_id: OWNER_ID,
+        local: true,
+        ..dm("hello")
+    };
+
+    bot.handle_message(&transport, &terminal).await;
+    bot.handle_message(&transport, &terminal).await;
+
+    let replies = transport.replies();
+    assert!(replies[0].starts_with("hi"));
+    assert!(replies[1].starts_with("again"));
+}
+
+#[tokio::test]
+async fn direct_messages_have_a_daily_quota() {
+    let (mock, mut bot) = setup().await;
+    bot.config.dm_daily_tokens = 10;
+    mock.push(common::text("hi"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &dm("hello")).await;
+    bot.handle_message(&transport, &dm("again")).await;
+    // the quota only counts direct messages
+    mock.push(common::text("sure"));
+    bot.handle_message(&transport, &message("? in a guild"))
+        .await;
+
+    let replies = transport.replies();
+    assert_eq!(
+        replies[1],
+        "You have used your daily quota of 10 tokens in direct messages"
+    );
+    assert!(replies[2].starts_with("sure"));
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn truncated_reply_can_be_continued() {
+    let (mock, bot) = setup().await;
+    mock.push(common::finished("Once upon", "MAX_TOKENS"));
+    mock.push(common::text("a time."));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? tell a story"))
+        .await;
+    assert!(transport.events().contains(&Event::Buttons(vec![
+        String::from("gemini:regenerate:1:42"),
+        String::from("gemini:continue:1:42"),
+        String::from("gemini:delete:1:42"),
+    ])));
+    press(&bot, &transport, USER_ID, "gemini:continue:1:42").await;
+
+    let body = mock.requests()[1].json();
+    assert_eq!(body["contents"].as_array().unwrap().len(), 3);
+    assert_eq!(body["contents"][1]["parts"][0]["text"], "Once upon");
+    assert!(transport.replies()[1].starts_with("a time."));
+}
+
+// presses a button under a reply in CHANNEL_ID
+async fn press(bot: &Bot, transport: &FakeTransport, author_id: u64, custom_id: &str) {
+    let msg = IncomingMessage {
+        author_id,
+        ..message("")
+    };
+    bot.handle_button(transport, &msg, custom_id).await;
+}
+
+#[tokio::test]
+async fn regenerate_replaces_last_reply() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("first take"));
+    mock.push(common::text("second take"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? a joke")).await;
+    press(&bot, &transport, USER_ID, "gemini:regenerate:1:42").await;
+
+    let body = mock.requests()[1].json();
+    assert_eq!(body["contents"].as_array().unwrap().len(), 1);
+    assert_eq!(body["contents"][0]["parts"][0]["text"], "a joke");
+    assert!(transport.replies()[1].starts_with("second take"));
+    assert!(transport.events().contains(&Event::Buttons(vec![
+        String::from("gemini:regenerate:3:42"),
+        String::from("gemini:delete:3:42"),
+    ])));
+}
+
+#[tokio::test]
+async fn failed_regenerate_makes_the_buttons_stale() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("one"));
+    mock.push(common::text("two"));
+    mock.push(common::safety());
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? first")).await;
+    bot.handle_message(&transport, &message("? second")).await;
+    press(&bot, &transport, USER_ID, "gemini:regenerate:2:42").await;
+    press(&bot, &transport, USER_ID, "gemini:delete:2:42").await;
+
+    // the first exchange isn't popped by the old reply's delete button
+    let conversation = bot.get_conversation(CHANNEL_ID).await;
+    let texts: Vec<String> = conversation
+        .lock()
+        .await
+        .contents
+        .iter()
+        .map(|content| content.text())
+        .collect();
+    assert_eq!(texts, vec!["first", "one"]);
+    assert_eq!(transport.events().last(), Some(&Event::Deleted));
+    assert_eq!(mock.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn old_replies_cannot_be_regenerated() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("one"));
+    mock.push(common::text("two"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? first")).await;
+    bot.handle_message(&transport, &message("? second")).await;
+    press(&bot, &transport, USER_ID, "gemini:regenerate:1:42").await;
+
+    assert_eq!(
+        transport.replies()[2],
+        "Only the latest reply can be regenerated or continued"
+    );
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn delete_removes_latest_exchange() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("oops"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? something"))
+        .await;
+    let reply_id = transport.events().len() as u64 - 2;
+    press(&bot, &transport, USER_ID + 1, "gemini:delete:1:42").await;
+    press(&bot, &transport, USER_ID, "gemini:delete:1:42").await;
+
+    assert_eq!(
+        transport.replies()[1],
+        "Only the person who asked can use these buttons"
+    );
+    assert_eq!(transport.events().last(), Some(&Event::DeletedId(reply_id)));
+    let conversation = bot.get_conversation(CHANNEL_ID).await;
+    assert!(conversation.lock().await.contents.is_empty());
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn delete_removes_every_part_of_a_long_reply() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text(&"a".repeat(2500)));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? write a lot"))
+        .await;
+    // replies are numbered by their position in the events
+    let reply_ids: Vec<u64> = transport
+        .events()
+        .iter()
+        .enumerate()
+        .filter(|(_, event)| matches!(event, Event::Reply(_)))
+        .map(|(index, _)| index as u64 + 1)
+        .collect();
+    press(&bot, &transport, USER_ID, "gemini:delete:1:42").await;
+
+    let deleted: Vec<u64> = transport
+        .events()
+        .iter()
+        .filter_map(|event| match event {
+            Event::DeletedId(id) => Some(*id),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(reply_ids.len(), 2);
+    assert_eq!(deleted, reply_ids);
+    assert!(!transport.events().contains(&Event::Deleted));
+}
+
+#[tokio::test]
+async fn edited_prompt_edits_the_reply() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("Paris"));
+    mock.push(common::text("Berlin"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? capital of frence"))
+        .await;
+    let reply_id = transport.events().len() as u64 - 2;
+    bot.handle_edit(&transport, &message("? capital of germany"))
+        .await;
+
+    let body = mock.requests()[1].json();
+    assert_eq!(body["contents"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        body["contents"][0]["parts"][0]["text"],
+        "capital of germany"
+    );
+    assert!(transport.events().iter().any(
+        |e| matches!(e, Event::Edit(id, text) if *id == reply_id && text.starts_with("Berlin"))
+    ));
+}
+
+#[tokio::test]
+async fn edited_direct_messages_are_limited_too() {
+    let (mock, mut bot) = setup().await;
+    bot.config.dm_mode = DmMode::Open;
+    bot.config.dm_daily_tokens = 10;
+    mock.push(common::text("Paris"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &dm("capital of frence"))
+        .await;
+    bot.handle_edit(&transport, &dm("capital of germany")).await;
+
+    assert_eq!(
+        transport.replies().last().unwrap(),
+        "You have used your daily quota of 10 tokens in direct messages"
+    );
+    assert_eq!(mock.requests().len(), 1);
+    // the answered prompt stays in the history
+    let conversation = bot.get_conversation(900).await;
+    assert_eq!(conversation.lock().await.contents.len(), 2);
+}
+
+#[tokio::test]
+async fn only_prompts_and_replies_are_tracked() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("hi"));
+    let transport = FakeTransport::default();
+
+    assert!(!bot.tracks_message(CHANNEL_ID, PROMPT_ID).await);
+    bot.handle_message(&transport, &message("? hello")).await;
+
+    assert!(bot.tracks_message(CHANNEL_ID, PROMPT_ID).await);
+    assert!(!bot.tracks_message(CHANNEL_ID, PROMPT_ID + 1).await);
+    assert!(!bot.tracks_message(CHANNEL_ID + 1, PROMPT_ID).await);
+}
+
+#[tokio::test]
+async fn unchanged_or_older_edits_are_ignored() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("one"));
+    mock.push(common::text("two"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? first")).await;
+    bot.handle_edit(&transport, &message("? first")).await;
+    let second = IncomingMessage {
+        message_id: PROMPT_ID + 1,
+        ..message("? second")
+    };
+    bot.handle_message(&transport, &second).await;
+    bot.handle_edit(&transport, &message("? first, edited"))
+        .await;
+
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn deleted_prompt_leaves_history() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("one"));
+    mock.push(common::text("two"));
+    mock.push(common::text("three"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? first")).await;
+    let second = IncomingMessage {
+        message_id: PROMPT_ID + 1,
+        ..message("? second")
+    };
+    bot.handle_message(&transport, &second).await;
+    bot.handle_delete(CHANNEL_ID, PROMPT_ID).await;
+    bot.handle_message(&transport, &message("? third")).await;
+
+    let body = mock.requests()[2].json();
+    let texts: Vec<&str> = body["contents"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["parts"][0]["text"].as_str().unwrap())
+        .collect();
+    assert_eq!(texts, vec!["second", "two", "third"]);
+}
+
+fn history(count: usize) -> Vec<HistoryMessage> {
+    (0..count)
+        .map(|i| HistoryMessage {
+            author_name: String::from("alice"),
+            content: format!("message number {}", i),
+            timestamp: 1_700_000_000 + i as i64 * 60,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn summarize_does_not_touch_the_conversation() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("They counted."));
+    let transport = FakeTransport {
+        history: history(3),
+        ..Default::default()
+    };
+
+    bot.handle_message(&transport, &message("!summarize 2"))
+        .await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![String::from("**Summary of 2 messages**\nThey counted.")]
+    );
+    let body = mock.requests()[0].json();
+    let prompt = body["contents"][0]["parts"][0]["text"].as_str().unwrap();
+    assert_eq!(
+        prompt,
+        "[2023-11-14 22:14] alice: message number 1\n[2023-11-14 22:15] alice: message number 2"
+    );
+    assert!(body.get("tools").is_none());
+    let conversation = bot.get_conversation(CHANNEL_ID).await;
+    assert!(conversation.lock().await.contents.is_empty());
+}
+
+#[tokio::test]
+async fn long_history_is_summarized_in_parts() {
+    let (mock, mut bot) = setup().await;
+    // about 60 characters per request
+    bot.config.summary_chunk_tokens = 15;
+    mock.push(common::text("first half"));
+    mock.push(common::text("second half"));
+    mock.push(common::text("everything"));
+    let transport = FakeTransport {
+        history: history(2),
+        ..Default::default()
+    };
+
+    bot.handle_message(&transport, &message("!summarize")).await;
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(
+        requests[2].json()["contents"][0]["parts"][0]["text"],
+        "first half\nsecond half"
+    );
+    assert!(transport.replies()[0].ends_with("everything"));
+}
+
+#[tokio::test]
+async fn translate_command_translates_the_replied_message() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("Bonjour tout le monde"));
+    let transport = FakeTransport::default();
+    let msg = IncomingMessage {
+        referenced: vec![QuotedMessage {
+            message_id: 0,
+            author_id: 5,
+            author_name: String::from("alice"),
+            content: String::from("Hello everyone"),
+            attachments: Vec::new(),
+        }],
+        ..message("!translate French")
+    };
+
+    bot.handle_message(&transport, &msg).await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![String::from("Bonjour tout le monde")]
+    );
+    let body = mock.requests()[0].json();
+    assert_eq!(body["contents"][0]["parts"][0]["text"], "Hello everyone");
+    assert!(body["systemInstruction"]["parts"][0]["text"]
+        .as_str()
+        .unwrap()
+        .contains("into French"));
+    let conversation = bot.get_conversation(CHANNEL_ID).await;
+    assert!(conversation.lock().await.contents.is_empty());
+}
+
+#[tokio::test]
+async fn translate_command_needs_text() {
+    let (mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("!translate German"))
+        .await;
+
+    assert!(transport.replies()[0].starts_with("Usage: !translate"));
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn flag_reaction_translates_the_message() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("Hola"));
+    let transport = FakeTransport::default();
+
+    bot.handle_reaction(&transport, &message("Hello"), "🇪🇸")
+        .await;
+
+    assert_eq!(transport.replies(), vec![String::from("Hola")]);
+    assert!(
+        mock.requests()[0].json()["systemInstruction"]["parts"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("into Spanish")
+    );
+}
+
+#[tokio::test]
+async fn other_reactions_are_ignored() {
+    let (mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+
+    bot.handle_reaction(&transport, &message("Hello"), "👍")
+        .await;
+    bot.handle_reaction(&transport, &message(""), "🇪🇸").await;
+
+    assert!(transport.events().is_empty());
+    assert!(mock.requests().is_empty());
+}
+
+const MOD_LOG: u64 = 77;
+
+// an admin message in the mod log channel
+fn in_mod_log(content: &str) -> IncomingMessage {
+    IncomingMessage {
+        channel_id: MOD_LOG,
+        ..admin(content)
+    }
+}
+
+async fn moderated_setup() -> (MockGemini, Bot) {
+    let (mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+    bot.handle_message(&transport, &in_mod_log("!moderation log"))
+        .await;
+    bot.handle_message(&transport, &admin("!moderation on"))
+        .await;
+    assert_eq!(
+        transport.replies(),
+        vec![
+            String::from("Flagged messages of this server now go to this channel"),
+            String::from(
+                "Moderation is now on in this channel, flagged messages go to the mod log"
+            ),
+        ]
+    );
+    (mock, bot)
+}
+
+#[tokio::test]
+async fn flagged_messages_go_to_the_mod_log() {
+    let (mock, bot) = moderated_setup().await;
+    mock.push(common::text(
+        "{\"category\": \"scam\", \"confidence\": 0.9, \"reason\": \"fake giveaway\"}",
+    ));
+    let transport = FakeTransport::default();
+
+    bot.moderate(&transport, &message("free nitro at example.com"))
+        .await;
+
+    assert_eq!(
+        transport.events(),
+        vec![Event::SendTo(
+            MOD_LOG,
+            format!(
+                "**Flagged scam** (90%) from <@{}> in <#{}>: fake giveaway\nhttps://discord.com/channels/1/{}/{}\n> free nitro at example.com\n",
+                USER_ID, CHANNEL_ID, CHANNEL_ID, PROMPT_ID
+            )
+        )]
+    );
+    let body = mock.requests()[0].json();
+    assert_eq!(
+        body["generationConfig"]["responseMimeType"],
+        "application/json"
+    );
+    // the author never asked the bot anything
+    let report = bot.usage.lock().await.report("", |_| true);
+    assert_eq!(
+        report.users.keys().collect::<Vec<_>>(),
+        vec![&MODERATION_USER]
+    );
+    assert!(report
+        .format(&Default::default())
+        .contains("**Top users**\nModeration: 15 tokens\n"));
+}
+
+#[tokio::test]
+async fn safety_ratings_flag_messages() {
+    let (mock, bot) = moderated_setup().await;
+    mock.push(common::raw(
+        200,
+        &serde_json::json!({
+            "candidates": [{
+                "finishReason": "SAFETY",
+                "safetyRatings": [
+                    {"category": "HARM_CATEGORY_HARASSMENT", "probability": "HIGH"}
+                ]
+            }]
+        })
+        .to_string(),
+    ));
+    let transport = FakeTransport::default();
+
+    bot.moderate(&transport, &message("you are awful")).await;
+
+    match &transport.events()[..] {
+        [Event::SendTo(MOD_LOG, report)] => assert!(report.starts_with("**Flagged harassment**")),
+        events => panic!("unexpected events: {:?}", events),
+    }
+    // the blocked answer has no usage metadata
+    let total = bot.usage.lock().await.report("", |_| true).total;
+    assert_eq!(total.requests, 1);
+    assert_eq!(total.prompt_tokens, 0);
+    assert_eq!(total.candidate_tokens, 0);
+}
+
+#[tokio::test]
+async fn unsure_verdicts_are_not_flagged() {
+    let (mock, bot) = moderated_setup().await;
+    mock.push(common::text(
+        "{\"category\": \"spam\", \"confidence\": 0.5, \"reason\": \"maybe\"}",
+    ));
+    let transport = FakeTransport::default();
+
+    bot.moderate(&transport, &message("check out my stream"))
+        .await;
+
+    assert!(transport.events().is_empty());
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn unmoderated_channels_are_not_checked() {
+    let (mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+    bot.handle_message(&transport, &in_mod_log("!moderation log"))
+        .await;
+    let transport = FakeTransport::default();
+
+    bot.moderate(&transport, &message("free nitro")).await;
+
+    assert!(transport.events().is_empty());
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn moderation_is_rate_limited() {
+    let (mock, mut bot) = moderated_setup().await;
+    bot.moderation_limits = tokio::sync::Mutex::new(RateLimiter::new(1));
+    mock.push(common::text("{\"category\": \"none\"}"));
+    let transport = FakeTransport::default();
+
+    bot.moderate(&transport, &message("hello")).await;
+    bot.moderate(&transport, &message("hello again")).await;
+
+    assert_eq!(mock.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn moderation_needs_a_mod_log() {
+    let (_mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &admin("!moderation on"))
+        .await;
+    bot.handle_message(&transport, &admin("!moderation")).await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![
+            String::from("Use !moderation log in the channel flagged messages should go to first"),
+            String::from("Moderation is off in this channel"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn mod_logs_stay_in_their_server() {
+    let (mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+    let other_server = IncomingMessage {
+        guild_id: Some(2),
+        ..in_mod_log("!moderation log")
+    };
+
+    bot.handle_message(&transport, &other_server).await;
+    bot.handle_message(&transport, &admin("!moderation on"))
+        .await;
+    bot.moderate(&transport, &message("free nitro")).await;
+
+    assert_eq!(
+        transport.replies()[1],
+        "Use !moderation log in the channel flagged messages should go to first"
+    );
+    assert!(!transport
+        .events()
+        .iter()
+        .any(|event| matches!(event, Event::SendTo(..))));
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn remembered_facts_are_added_for_their_user_only() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("Hi Sam"));
+    mock.push(common::text("Hi"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("!memory on")).await;
+    bot.handle_message(&transport, &message("!memory add Call me Sam"))
+        .await;
+    bot.handle_message(&transport, &message("? hello")).await;
+    let other = IncomingMessage {
+        author_id: 43,
+        ..message("? hello")
+    };
+    bot.handle_message(&transport, &other).await;
+
+    let requests = mock.requests();
+    let instruction = requests[0].json()["systemInstruction"].clone();
+    assert!(instruction["parts"][0]["text"]
+        .as_str()
+        .unwrap()
+        .ends_with("\n- Call me Sam"));
+    assert!(requests[1].json().get("systemInstruction").is_none());
+    let conversation = bot.get_conversation(CHANNEL_ID).await;
+    assert!(conversation.lock().await.systemInstruction.is_none());
+}
+
+#[tokio::test]
+async fn memory_commands_manage_facts() {
+    let (_mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("!memory add Uses Arch"))
+        .await;
+    bot.handle_message(&transport, &message("!memory on")).await;
+    bot.handle_message(&transport, &message("!memory add Uses Arch"))
+        .await;
+    bot.handle_message(&transport, &message("!memory add Call me Sam"))
+        .await;
+    bot.handle_message(&transport, &message("!memory forget 1"))
+        .await;
+    bot.handle_message(&transport, &message("!memory list"))
+        .await;
+
+    let replies = transport.replies();
+    assert_eq!(replies[0], "Memory is off, turn it on with !memory on");
+    assert_eq!(replies[2], "I will remember: Uses Arch");
+    assert_eq!(replies[4], "Forgot: Uses Arch");
+    assert_eq!(replies[5], "1. Call me Sam");
+}
+
+#[tokio::test]
+async fn memory_is_only_exported_in_direct_messages() {
+    let (_mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("!memory on")).await;
+    bot.handle_message(&transport, &message("!memory add Call me Sam"))
+        .await;
+    bot.handle_message(&transport, &message("!memory export"))
+        .await;
+    bot.handle_message(&transport, &dm("!memory export")).await;
+
+    let events = transport.events();
+    assert_eq!(
+        transport.replies().last().unwrap(),
+        "Your facts are private, use !memory export in a direct message"
+    );
+    match events.last() {
+        Some(Event::File(filename, content)) => {
+            assert_eq!(filename, &format!("memory-{}.json", USER_ID));
+            assert!(content.contains("Call me Sam"));
+        }
+        event => panic!("unexpected event: {:?}", event),
+    }
+}
+
+#[tokio::test]
+async fn gemini_can_save_facts() {
+    let (mock, bot) = setup().await;
+    mock.push(common::function_call(
+        "remember_fact",
+        serde_json::json!({"fact": "Uses Arch"}),
+    ));
+    mock.push(common::text("Noted"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("!memory on")).await;
+    bot.handle_message(&transport, &message("? remember that I use Arch"))
+        .await;
+
+    let facts = bot.memory.lock().await.facts(USER_ID).to_vec();
+    assert_eq!(facts.len(), 1);
+    assert_eq!(facts[0].text, "Uses Arch");
+}
+
+#[tokio::test]
+async fn answers_cite_the_indexed_docs() {
+    let (mock, mut bot) = setup().await;
+    let dir = std::env::temp_dir().join(format!("handler-docs-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("setup.md"),
+        "# Install\n\nRun make install.\n\n# Colors\n\nBlue is the default.",
+    )
+    .unwrap();
+    bot.config.docs_dir = Some(dir.to_string_lossy().to_string());
+    bot.config.context_footer = false;
+    mock.push(common::embeddings(&[&[1.0, 0.0], &[0.0, 1.0]]));
+    mock.push(common::embeddings(&[&[0.9, 0.1]]));
+    mock.push(common::text("Run make install [setup.md#Install]"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &admin("!reindex")).await;
+    bot.handle_message(&transport, &message("? how do I install it"))
+        .await;
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        transport.replies(),
+        vec![
+            String::from("Indexed 2 chunks from 1 files"),
+            String::from("Run make install [setup.md#Install]\n-# Sources: setup.md#Install"),
+        ]
+    );
+    let requests = mock.requests();
+    assert!(requests[0]
+        .path
+        .ends_with("/models/text-embedding-004:batchEmbedContents"));
+    assert_eq!(
+        requests[1].json()["requests"][0]["taskType"],
+        "RETRIEVAL_QUERY"
+    );
+    let instruction = requests[2].json()["systemInstruction"]["parts"][0]["text"].clone();
+    assert!(instruction
+        .as_str()
+        .unwrap()
+        .ends_with("[setup.md#Install]\n# Install\n\nRun make install."));
+    let conversation = bot.get_conversation(CHANNEL_ID).await;
+    assert!(conversation.lock().await.systemInstruction.is_none());
+}
+
+#[tokio::test]
+async fn reindex_needs_a_docs_dir() {
+    let (mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &admin("!reindex")).await;
+    bot.handle_message(&transport, &message("!reindex")).await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![String::from("Set DOCS_DIR to answer from documents")]
+    );
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn answers_use_the_channels_knowledge_base() {
+    let (mock, mut bot) = setup().await;
+    bot.config.context_footer = false;
+    mock.push(common::embeddings(&[&[1.0, 0.0]]));
+    mock.push(common::embeddings(&[&[0.9, 0.1]]));
+    mock.push(common::text("Be nice."));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &admin("!kb add Rules: be nice to everyone"))
+        .await;
+    bot.handle_message(&transport, &message("? what are the rules"))
+        .await;
+    bot.handle_message(&transport, &message("!kb")).await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![
+            String::from("Added #1 Rules (19 characters)"),
+            String::from("Be nice.\n-# Knowledge base: #1 Rules"),
+            String::from("#1 Rules (note, 19 characters)\n19 / 100000 characters used"),
+        ]
+    );
+    let requests = mock.requests();
+    assert_eq!(
+        requests[0].json()["requests"][0]["taskType"],
+        "RETRIEVAL_DOCUMENT"
+    );
+    let instruction = requests[2].json()["systemInstruction"]["parts"][0]["text"].clone();
+    assert!(instruction
+        .as_str()
+        .unwrap()
+        .ends_with("[#1 Rules]\nbe nice to everyone"));
+}
+
+#[tokio::test]
+async fn knowledge_base_is_per_channel() {
+    let (mock, mut bot) = setup().await;
+    bot.config.context_footer = false;
+    mock.push(common::embeddings(&[&[1.0, 0.0]]));
+    mock.push(common::text("No idea."));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &admin("!kb add Rules: be nice"))
+        .await;
+    let elsewhere = IncomingMessage {
+        channel_id: CHANNEL_ID + 1,
+        ..message("? what are the rules")
+    };
+    bot.handle_message(&transport, &elsewhere).await;
+
+    // the other channel has no material, so its question isn't embedded
+    assert_eq!(mock.requests().len(), 2);
+    assert_eq!(transport.replies()[1], "No idea.");
+}
+
+#[tokio::test]
+async fn only_admins_change_the_knowledge_base() {
+    let (mock, mut bot) = setup().await;
+    bot.config.kb_max_chars = 10;
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("!kb add Rules: be nice"))
+        .await;
+    bot.handle_message(&transport, &admin("!kb add Rules: be nice to everyone"))
+        .await;
+    bot.handle_message(&transport, &admin("!kb remove 3")).await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![
+            String::from("Only admins can change the knowledge base"),
+            String::from(
+                "The knowledge base of this channel would have 19 characters, the limit is 10"
+            ),
+            String::from("Usage: !kb remove <number from !kb list>"),
+        ]
+    );
+    assert!(mock.requests().is_empty());
+}
+
+#[tokio::test]
+async fn pins_and_files_are_added_to_the_knowledge_base() {
+    let (mock, bot) = setup().await;
+    mock.push(common::embeddings(&[&[1.0, 0.0]]));
+    mock.push(common::embeddings(&[&[0.0, 1.0]]));
+    mock.push(common::embeddings(&[&[0.0, 1.0]]));
+    let transport = FakeTransport {
+        file: Some(b"Meetings are on Fridays.".to_vec()),
+        pins: history(2),
+        ..Default::default()
+    };
+    let upload = IncomingMessage {
+        attachments: vec![
+            Attachment {
+                url: String::from("https://cdn.example/faq.txt"),
+                filename: String::from("faq.txt"),
+                content_type: Some(String::from("text/plain; charset=utf-8")),
+            },
+            image(Some("image/png")),
+        ],
+        ..admin("!kb add")
+    };
+
+    bot.handle_message(&transport, &upload).await;
+    bot.handle_message(&transport, &admin("!kb pins")).await;
+    bot.handle_message(&transport, &admin("!kb pins")).await;
+    bot.handle_message(&transport, &admin("!kb list")).await;
+
+    assert_eq!(
+        transport.replies(),
+        vec![
+            String::from("Added #1 faq.txt (24 characters)\nUnsupported file type: cat.png"),
+            String::from("Added #2 Pinned messages (48 characters)"),
+            String::from("Added #3 Pinned messages (48 characters)"),
+            String::from(
+                "#1 faq.txt (file, 24 characters)\n#3 Pinned messages (pin, 48 characters)\n72 / 100000 characters used"
+            ),
+        ]
+    );
+}
+
+fn transcript() -> Attachment {
+    Attachment {
+        url: String::from("https://cdn.example/conversation.json"),
+        filename: String::from("conversation.json"),
+        content_type: Some(String::from("application/json")),
+    }
+}
+
+#[tokio::test]
+async fn conversation_is_exported_and_imported() {
+    let (mock, mut bot) = setup().await;
+    bot.config.context_footer = false;
+    mock.push(common::text("Hi <b>there</b>"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? hello")).await;
+    bot.handle_message(&transport, &message("!export")).await;
+    bot.handle_message(&transport, &message("!export html"))
+        .await;
+    bot.handle_message(&transport, &message("!export json"))
+        .await;
+
+    let files: Vec<(String, String)> = transport
+        .events()
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::File(filename, content) => Some((filename, content)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(files[0].0, "conversation-7.md");
+    assert!(files[0].1.ends_with("**Gemini**\n\nHi <b>there</b>\n"));
+    assert_eq!(files[1].0, "conversation-7.html");
+    assert!(files[1].1.contains("Hi &lt;b&gt;there&lt;/b&gt;"));
+    assert_eq!(files[2].0, "conversation-7.json");
+
+    // the json export loads into another channel
+    let other = FakeTransport {
+        file: Some(files[2].1.clone().into_bytes()),
+        ..Default::default()
+    };
+    let import = IncomingMessage {
+        channel_id: CHANNEL_ID + 1,
+        attachments: vec![transcript()],
+        ..admin("!import")
+    };
+    bot.handle_message(&other, &import).await;
+
+    assert_eq!(other.replies(), vec![String::from("Imported 2 turns")]);
+    let original = bot.get_conversation(CHANNEL_ID).await;
+    let imported = bot.get_conversation(CHANNEL_ID + 1).await;
+    let original = original.lock().await;
+    let imported = imported.lock().await;
+    assert_eq!(imported.contents.len(), 2);
+    assert_eq!(imported.contents[0].text(), original.contents[0].text());
+    assert_eq!(imported.contents[1].text(), "Hi <b>there</b>");
+}
+
+#[tokio::test]
+async fn trimmed_conversation_is_imported_again() {
+    let (mock, mut bot) = setup().await;
+    bot.config.context_footer = false;
+    for i in 0..10 {
+        mock.push(common::text(&format!("answer {}", i)));
+    }
+    let transport = FakeTransport::default();
+
+    for i in 0..10 {
+        bot.handle_message(&transport, &message(&format!("? question {}", i)))
+            .await;
+    }
+    bot.handle_message(&transport, &message("!export json"))
+        .await;
+
+    let Some(Event::File(_, json)) = transport.events().pop() else {
+        panic!("no file was sent");
+    };
+    let other = FakeTransport {
+        file: Some(json.into_bytes()),
+        ..Default::default()
+    };
+    let import = IncomingMessage {
+        channel_id: CHANNEL_ID + 1,
+        attachments: vec![transcript()],
+        ..admin("!import")
+    };
+    bot.handle_message(&other, &import).await;
+
+    let conversation = bot.get_conversation(CHANNEL_ID).await;
+    let turns = conversation.lock().await.contents.len();
+    assert_eq!(
+        other.replies(),
+        vec![format!("Imported {} turns", turns / 2 * 2)]
+    );
+    let imported = bot.get_conversation(CHANNEL_ID + 1).await;
+    let imported = imported.lock().await;
+    assert_eq!(imported.contents[0].role, "user");
+    assert_eq!(imported.contents.last().unwrap().text(), "answer 9");
+}
+
+#[tokio::test]
+async fn prompts_keep_their_message_when_old_turns_are_dropped() {
+    let (mock, mut bot) = setup().await;
+    bot.config.context_footer = false;
+    for i in 0..10 {
+        mock.push(common::text(&format!("answer {}", i)));
+    }
+    let transport = FakeTransport::default();
+
+    for i in 0..10 {
+        let prompt = IncomingMessage {
+            message_id: 100 + i,
+            ..message(&format!("? question {}", i))
+        };
+        bot.handle_message(&transport, &prompt).await;
+    }
+    bot.handle_delete(CHANNEL_ID, 109).await;
+
+    let conversation = bot.get_conversation(CHANNEL_ID).await;
+    let conversation = conversation.lock().await;
+    assert_eq!(conversation.contents.len(), 16);
+    assert_eq!(conversation.contents[14].message_ids, vec![108]);
+    assert_eq!(conversation.contents[15].text(), "answer 8");
+}
+
+#[tokio::test]
+async fn invalid_imports_keep_the_conversation() {
+    let (mock, bot) = setup().await;
+    mock.push(common::text("kept"));
+    let transport = FakeTransport {
+        file: Some(br#"{"contents": [{"role": "model", "parts": [{"text": "hi"}]}]}"#.to_vec()),
+        ..Default::default()
+    };
+    let import = IncomingMessage {
+        attachments: vec![transcript()],
+        ..admin("!import")
+    };
+
+    bot.handle_message(&transport, &message("? hello")).await;
+    bot.handle_message(&transport, &import).await;
+    bot.handle_message(&transport, &admin("!import")).await;
+    bot.handle_message(
+        &transport,
+        &IncomingMessage {
+            attachments: vec![transcript()],
+            ..message("!import")
+        },
+    )
+    .await;
+
+    let replies = transport.replies();
+    assert_eq!(
+        replies[1..],
+        [
+            String::from("Turn 1 should be from the user, turns alternate starting with the user"),
+            String::from("Attach a JSON transcript from !export json to !import"),
+            String::from("Only admins can import conversations"),
+        ]
+    );
+    let conversation = bot.get_conversation(CHANNEL_ID).await;
+    assert_eq!(conversation.lock().await.contents.len(), 2);
+}
+
+#[tokio::test]
+async fn empty_conversation_is_not_exported() {
+    let (_mock, bot) = setup().await;
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("!export")).await;
+    bot.handle_message(&transport, &message("!export pdf"))
+        .await;
+
+    assert_eq!(
+        transport.events(),
+        vec![
+            Event::Reply(String::from("There is no conversation to export yet")),
+            Event::Reply(String::from("Usage: !export [md|json|html]")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn admins_edit_the_history() {
+    let (mock, mut bot) = setup().await;
+    bot.config.context_footer = false;
+    mock.push(common::text("first answer"));
+    mock.push(common::text("second answer"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? one")).await;
+    bot.handle_message(&transport, &message("? two")).await;
+    bot.handle_message(&transport, &message("!history pin 1"))
+        .await;
+    bot.handle_message(&transport, &admin("!history pin 1"))
+        .await;
+    bot.handle_message(&transport, &admin("!history delete 4"))
+        .await;
+    bot.handle_message(&transport, &admin("!history delete 9"))
+        .await;
+    bot.handle_message(&transport, &message("!history")).await;
+
+    let replies = transport.replies();
+    assert_eq!(
+        replies[2..6],
+        [
+            String::from("Only admins can change the history"),
+            String::from("Pinned turn #1, it won't be dropped from the history"),
+            String::from("Deleted turns #3 and #4"),
+            String::from("Usage: !history delete <number from !history>"),
+        ]
+    );
+    assert!(replies[6].starts_with("**#1** user · "));
+    assert!(replies[6].contains(" · pinned\n> "));
+    assert!(replies[6].contains("**#2** model · ~3 tokens\n> first answer"));
+    let conversation = bot.get_conversation(CHANNEL_ID).await;
+    let conversation = conversation.lock().await;
+    assert_eq!(conversation.contents.len(), 2);
+    assert!(conversation.contents[0].pinned);
+}
+
+#[tokio::test]
+async fn edited_history_is_imported_again() {
+    let (mock, mut bot) = setup().await;
+    bot.config.context_footer = false;
+    mock.push(common::text("first answer"));
+    mock.push(common::text("second answer"));
+    let transport = FakeTransport::default();
+
+    bot.handle_message(&transport, &message("? one")).await;
+    bot.handle_message(&transport, &message("? two")).await;
+    bot.handle_message(&transport, &admin("!history delete 2"))
+        .await;
+    bot.handle_message(&transport, &message("!export json"))
+        .await;
+
+    let Some(Event::File(_, json)) = transport.events().pop() else {
+        panic!("no file was sent");
+    };
+    let other = FakeTransport {
+        file: Some(json.into_bytes()),
+        ..Default::default()
+    };
+    let import = IncomingMessage {
+        channel_id: CHANNEL_ID + 1,
+        attachments: vec![transcript()],
+        ..admin("!import")
+    };
+    bot.handle_message(&other, &import).await;
+
+    assert_eq!(transport.replies()[2], "Deleted turns #1 and #2");
+    assert_eq!(other.replies(), vec![String::from("Imported 2 turns")]);
+    let imported = bot.get_conversation(CHANNEL_ID + 1).await;
+    let imported = imported.lock().await;
+    assert_eq!(imported.contents[0].text(), "two");
+    assert_eq!(imported.contents[1].text(), "second answer");
+}
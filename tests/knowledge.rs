@@ -0,0 +1,74 @@
+use rust_discord_bot::knowledge::{new_entry, parse_note, KbEntry, KnowledgeBase};
+
+fn embedded(title: &str, kind: &str, text: &str, embedding: Vec<f32>) -> KbEntry {
+    let mut entry = new_entry(title, kind, text, 1, 0);
+    for chunk in &mut entry.chunks {
+        chunk.embedding = embedding.clone();
+    }
+    entry
+}
+
+#[test]
+fn notes_are_split_into_title_and_text() {
+    assert_eq!(
+        parse_note("Rules: be nice"),
+        (String::from("Rules"), String::from("be nice"))
+    );
+    assert_eq!(
+        parse_note("see https://example.com"),
+        (
+            String::from("see https://example.com"),
+            String::from("see https://example.com")
+        )
+    );
+    let (title, text) = parse_note(&"word ".repeat(20));
+    assert_eq!(title, format!("{}...", "word ".repeat(8)));
+    assert_eq!(text, "word ".repeat(20).trim());
+}
+
+#[test]
+fn entries_are_numbered_per_channel() {
+    let mut kb = KnowledgeBase::default();
+
+    assert_eq!(kb.add(1, new_entry("a", "note", "first", 1, 0)), 1);
+    assert_eq!(kb.add(1, new_entry("b", "note", "second", 1, 0)), 2);
+    assert_eq!(kb.add(2, new_entry("c", "note", "other", 1, 0)), 1);
+    assert_eq!(kb.remove(1, 1).unwrap().title, "a");
+    assert_eq!(kb.add(1, new_entry("d", "note", "third", 1, 0)), 3);
+
+    assert!(kb.remove(1, 1).is_none());
+    assert_eq!(kb.channel(1).unwrap().entries.len(), 2);
+    kb.clear(1);
+    assert!(kb.channel(1).is_none());
+    assert!(kb.channel(2).is_some());
+}
+
+#[test]
+fn size_limit_ignores_replaced_entries() {
+    let mut kb = KnowledgeBase::default();
+    kb.add(1, new_entry("pins", "pin", &"a".repeat(60), 1, 0));
+    kb.add(1, new_entry("note", "note", &"b".repeat(30), 1, 0));
+
+    assert!(kb.check_size(1, 10, None, 100).is_ok());
+    assert_eq!(
+        kb.check_size(1, 20, None, 100).unwrap_err(),
+        "The knowledge base of this channel would have 110 characters, the limit is 100"
+    );
+    assert!(kb.check_size(1, 70, Some("pin"), 100).is_ok());
+    assert!(kb.check_size(2, 100, None, 100).is_ok());
+}
+
+#[test]
+fn search_only_finds_the_channels_material() {
+    let mut kb = KnowledgeBase::default();
+    kb.add(1, embedded("Rules", "note", "be nice", vec![1.0, 0.0]));
+    kb.add(1, embedded("Colors", "note", "blue", vec![0.0, 1.0]));
+    kb.add(2, embedded("Other", "note", "elsewhere", vec![1.0, 0.0]));
+
+    let found = kb.search(1, &[0.9, 0.1], 4, 0.5);
+
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].0.label(), "#1 Rules");
+    assert_eq!(found[0].1.text, "be nice");
+    assert!(kb.search(3, &[1.0, 0.0], 4, 0.5).is_empty());
+}
@@ -0,0 +1,63 @@
+use rust_discord_bot::memory::{MemoryStore, MAX_FACTS};
+
+const USER_ID: u64 = 42;
+
+#[test]
+fn facts_need_memory_turned_on() {
+    let mut store = MemoryStore::default();
+
+    assert!(store.add(USER_ID, "Uses Arch", 0).is_err());
+    store.set_enabled(USER_ID, true);
+    store.add(USER_ID, "Uses Arch", 0).unwrap();
+    // the same fact is only kept once
+    store.add(USER_ID, " Uses Arch ", 0).unwrap();
+
+    assert_eq!(store.facts(USER_ID).len(), 1);
+    store.set_enabled(USER_ID, false);
+    assert!(store.facts(USER_ID).is_empty());
+}
+
+#[test]
+fn facts_are_limited() {
+    let mut store = MemoryStore::default();
+    store.set_enabled(USER_ID, true);
+    for i in 0..MAX_FACTS {
+        store.add(USER_ID, &format!("fact {}", i), 0).unwrap();
+    }
+
+    assert!(store.add(USER_ID, "one too many", 0).is_err());
+    assert!(store.add(USER_ID, &"a".repeat(301), 0).is_err());
+    assert!(store.add(USER_ID, "  ", 0).is_err());
+}
+
+#[test]
+fn facts_are_forgotten_by_number() {
+    let mut store = MemoryStore::default();
+    store.set_enabled(USER_ID, true);
+    store.add(USER_ID, "Call me Sam", 0).unwrap();
+    store.add(USER_ID, "Uses Arch", 0).unwrap();
+
+    assert!(store.forget(USER_ID, 0).is_none());
+    assert!(store.forget(USER_ID, 3).is_none());
+    assert_eq!(store.forget(USER_ID, 1).unwrap().text, "Call me Sam");
+    assert_eq!(
+        store.instruction(USER_ID).unwrap(),
+        "The message is from <@42>, who asked you to remember these facts about them:\n- Uses Arch"
+    );
+    store.clear(USER_ID);
+    assert_eq!(store.instruction(USER_ID), None);
+}
+
+#[test]
+fn export_has_everything_about_the_user() {
+    let mut store = MemoryStore::default();
+    store.set_enabled(USER_ID, true);
+    store.add(USER_ID, "Uses Arch", 1_700_000_000).unwrap();
+
+    let export: serde_json::Value = serde_json::from_str(&store.export(USER_ID)).unwrap();
+
+    assert_eq!(export["user_id"], "42");
+    assert_eq!(export["enabled"], true);
+    assert_eq!(export["facts"][0]["text"], "Uses Arch");
+    assert_eq!(export["facts"][0]["created"], 1_700_000_000);
+}
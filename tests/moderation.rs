@@ -0,0 +1,48 @@
+use rust_discord_bot::moderation::{parse_verdict, rating_verdict, RateLimiter, Verdict};
+use rust_discord_bot::structs::SafetyRatings;
+
+fn rating(category: &str, probability: &str) -> SafetyRatings {
+    SafetyRatings {
+        category: category.to_string(),
+        probability: probability.to_string(),
+    }
+}
+
+#[test]
+fn verdicts_are_parsed() {
+    let verdict = parse_verdict(
+        "```json\n{\"category\": \"scam\", \"confidence\": 0.95, \"reason\": \"fake nitro\"}\n```",
+    )
+    .unwrap();
+
+    assert_eq!(verdict.category, "scam");
+    assert!(verdict.is_flagged(0.8));
+    assert!(!verdict.is_flagged(0.99));
+    assert_eq!(parse_verdict("not json"), None);
+    assert!(!Verdict::default().is_flagged(0.0));
+}
+
+#[test]
+fn strongest_rating_wins() {
+    let verdict = rating_verdict(&[
+        rating("HARM_CATEGORY_DANGEROUS_CONTENT", "LOW"),
+        rating("HARM_CATEGORY_HATE_SPEECH", "HIGH"),
+        rating("HARM_CATEGORY_SEXUALLY_EXPLICIT", "NEGLIGIBLE"),
+    ]);
+
+    assert_eq!(verdict.category, "harassment");
+    assert_eq!(verdict.confidence, 0.9);
+    assert_eq!(rating_verdict(&[]), Verdict::default());
+}
+
+#[test]
+fn checks_are_rate_limited_per_channel() {
+    let mut limiter = RateLimiter::new(2);
+
+    assert!(limiter.allow(1, 100));
+    assert!(limiter.allow(1, 110));
+    assert!(!limiter.allow(1, 120));
+    assert!(limiter.allow(2, 120));
+    // a minute after the first check
+    assert!(limiter.allow(1, 160));
+}
@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+
+use rust_discord_bot::persist::{load_json, save_json};
+
+fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("{}-{}.json", name, std::process::id()));
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn saved_stores_are_loaded_again() {
+    let path = temp_path("persist-round-trip");
+    let store = HashMap::from([(String::from("a"), 1), (String::from("b"), 2)]);
+    save_json(&path, &store, "test store", false);
+    let loaded: HashMap<String, i32> = load_json(&path, "test store");
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, store);
+}
+
+#[test]
+fn missing_and_broken_files_start_empty() {
+    let path = temp_path("persist-broken");
+    let missing: HashMap<String, i32> = load_json(&path, "test store");
+    assert!(missing.is_empty());
+    std::fs::write(&path, "not json").unwrap();
+    let broken: HashMap<String, i32> = load_json(&path, "test store");
+    std::fs::remove_file(&path).unwrap();
+    assert!(broken.is_empty());
+}
+
+#[test]
+fn stores_without_a_path_stay_in_memory() {
+    save_json("", &vec![1, 2, 3], "test store", true);
+    let loaded: Vec<i32> = load_json("", "test store");
+    assert!(loaded.is_empty());
+}
@@ -0,0 +1,70 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use rust_discord_bot::redact::{redact, RedactingMakeWriter};
+use tracing_subscriber::fmt::MakeWriter;
+
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for Buffer {
+    type Writer = Buffer;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+impl Buffer {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+#[test]
+fn masks_every_secret() {
+    let secrets = vec![String::from("gemini-key"), String::from("discord-token")];
+    assert_eq!(
+        redact(
+            "url?key=gemini-key token=discord-token again gemini-key",
+            &secrets
+        ),
+        "url?key=[REDACTED] token=[REDACTED] again [REDACTED]"
+    );
+}
+
+#[test]
+fn ignores_empty_secrets() {
+    assert_eq!(redact("hello", &[String::new()]), "hello");
+}
+
+#[test]
+fn subscriber_output_is_redacted() {
+    let buffer = Buffer::default();
+    let writer = RedactingMakeWriter::new(buffer.clone(), vec![String::from("gemini-key")]);
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(writer)
+        .with_ansi(false)
+        .finish();
+
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::error!("Error sending POST request: https://example/?key=gemini-key");
+        tracing::info!("nothing secret here");
+    });
+
+    let text = buffer.text();
+    assert!(!text.contains("gemini-key"));
+    assert!(text.contains("https://example/?key=[REDACTED]"));
+    assert!(text.contains("nothing secret here"));
+}
@@ -0,0 +1,28 @@
+use rust_discord_bot::settings::{BotThread, Settings};
+
+fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("{}-{}.json", name, std::process::id()));
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn thread_activity_is_saved_by_flush() {
+    let path = temp_path("settings-flush");
+    let mut settings = Settings::load(&path);
+    settings.threads.insert(
+        5,
+        BotThread {
+            parent_id: 1,
+            last_activity: 100,
+        },
+    );
+    settings.save();
+
+    assert!(settings.touch_thread(5, 200));
+    assert!(!settings.touch_thread(6, 200));
+    assert_eq!(Settings::load(&path).threads[&5].last_activity, 100);
+    settings.flush();
+    let saved = Settings::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(saved.threads[&5].last_activity, 200);
+}
@@ -0,0 +1,39 @@
+use rust_discord_bot::summarize::{chunk_lines, parse_range, DEFAULT_MESSAGES, MAX_MESSAGES};
+
+const NOW: i64 = 1_700_000_000;
+
+#[test]
+fn ranges_are_parsed() {
+    assert_eq!(parse_range("", NOW), Ok((DEFAULT_MESSAGES, None)));
+    assert_eq!(parse_range("250", NOW), Ok((250, None)));
+    assert_eq!(parse_range("2h", NOW), Ok((MAX_MESSAGES, Some(NOW - 7200))));
+    assert_eq!(
+        parse_range("2023-11-14 22:00", NOW),
+        Ok((MAX_MESSAGES, Some(1_699_999_200)))
+    );
+    assert!(parse_range("0", NOW).is_err());
+    assert!(parse_range("yesterday", NOW).is_err());
+    assert!(parse_range("0m", NOW).is_err());
+    assert!(parse_range("-5m", NOW).is_err());
+    assert!(parse_range("999999999999999d", NOW).is_err());
+    assert!(parse_range("100000d", NOW).is_err());
+    assert_eq!(
+        parse_range("-5m", NOW),
+        Err(String::from(
+            "Usage: !summarize [amount of messages | 30m | 2h | 1d | YYYY-MM-DD HH:MM]"
+        ))
+    );
+}
+
+#[test]
+fn lines_are_chunked() {
+    let lines: Vec<String> = ["aaaa", "bbbb", "cccccccccc", "dd"]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+
+    assert_eq!(
+        chunk_lines(&lines, 9),
+        vec!["aaaa\nbbbb", "cccccccccc", "dd"]
+    );
+}
@@ -0,0 +1,222 @@
+mod common;
+
+use common::MockGemini;
+use rust_discord_bot::gemini::Gemini;
+use rust_discord_bot::structs::{Conversation, FunctionCall, InlineData};
+use rust_discord_bot::tools::{Tool, ToolContext, ToolPermission, ToolRegistry};
+use serde_json::{json, Value};
+use serenity::async_trait;
+
+const MODEL: &str = "gemini-1.5-flash-001";
+
+struct Echo;
+
+#[async_trait]
+impl Tool for Echo {
+    fn name(&self) -> &str {
+        "echo"
+    }
+
+    fn description(&self) -> &str {
+        "Returns its text"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {"text": {"type": "string"}},
+            "required": ["text"]
+        })
+    }
+
+    async fn call(&self, _ctx: &ToolContext, args: &Value) -> Result<Value, String> {
+        args["text"]
+            .as_str()
+            .map(|text| json!(text))
+            .ok_or_else(|| String::from("text is missing"))
+    }
+}
+
+struct Secret;
+
+#[async_trait]
+impl Tool for Secret {
+    fn name(&self) -> &str {
+        "secret"
+    }
+
+    fn description(&self) -> &str {
+        "Only for admins"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({"type": "object", "properties": {}})
+    }
+
+    fn permission(&self) -> ToolPermission {
+        ToolPermission::Admin
+    }
+
+    async fn call(&self, _ctx: &ToolContext, _args: &Value) -> Result<Value, String> {
+        Ok(json!("42"))
+    }
+}
+
+fn registry(max_steps: usize) -> ToolRegistry {
+    let mut registry = ToolRegistry::new(Vec::new(), max_steps);
+    registry.register(Echo);
+    registry.register(Secret);
+    registry
+}
+
+async fn setup() -> (MockGemini, Gemini) {
+    let mock = MockGemini::start().await;
+    let gemini = Gemini::new(String::from("test-key"), MODEL.to_string(), &mock.base_url);
+    (mock, gemini)
+}
+
+#[tokio::test]
+async fn tool_results_are_sent_back() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::function_call("echo", json!({"text": "pong"})));
+    mock.push(common::text("It said pong"));
+
+    let mut conversation = Conversation::default();
+    let (text, usage) = gemini
+        .send_msg_with_tools(
+            MODEL,
+            &mut conversation,
+            "ping".to_string(),
+            vec![],
+            vec![],
+            &registry(5),
+            &ToolContext::default(),
+        )
+        .await;
+
+    assert_eq!(text, "It said pong");
+    assert_eq!(usage.promptTokenCount, 20);
+    assert_eq!(usage.totalTokenCount, 15);
+
+    let requests = mock.requests();
+    let first = requests[0].json();
+    let names: Vec<&str> = first["tools"][0]["functionDeclarations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["echo"]);
+
+    let second = requests[1].json();
+    assert_eq!(
+        second["contents"][1]["parts"][0]["functionCall"]["name"],
+        "echo"
+    );
+    assert_eq!(second["contents"][2]["role"], "function");
+    assert_eq!(
+        second["contents"][2]["parts"][0]["functionResponse"]["response"]["result"],
+        "pong"
+    );
+
+    // the history only keeps the question and the answer
+    assert_eq!(conversation.contents.len(), 2);
+    assert_eq!(conversation.contents[1].parts[0].text, "It said pong");
+}
+
+#[tokio::test]
+async fn images_are_kept_while_tools_run() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::function_call("echo", json!({"text": "cat"})));
+    mock.push(common::text("It is a cat"));
+
+    let mut conversation = Conversation::default();
+    let image = InlineData {
+        mimeType: "image/png".to_string(),
+        data: "aGVsbG8=".to_string(),
+    };
+    let (text, _) = gemini
+        .send_msg_with_tools(
+            MODEL,
+            &mut conversation,
+            "what is this?".to_string(),
+            vec![image],
+            vec![],
+            &registry(5),
+            &ToolContext::default(),
+        )
+        .await;
+
+    assert_eq!(text, "It is a cat");
+    let second = mock.requests()[1].json();
+    assert_eq!(
+        second["contents"][0]["parts"][0]["inlineData"]["mimeType"],
+        "image/png"
+    );
+    assert_eq!(second["contents"][0]["parts"][1]["text"], "what is this?");
+
+    // the answer is kept without the image
+    assert_eq!(conversation.contents[0].parts.len(), 1);
+    assert_eq!(conversation.contents[0].parts[0].text, "what is this?");
+}
+
+#[tokio::test]
+async fn tool_loop_is_bounded() {
+    let (mock, gemini) = setup().await;
+    mock.push(common::function_call("echo", json!({"text": "a"})));
+    mock.push(common::function_call("echo", json!({"text": "b"})));
+
+    let mut conversation = Conversation::default();
+    let (text, usage) = gemini
+        .send_msg_with_tools(
+            MODEL,
+            &mut conversation,
+            "loop".to_string(),
+            vec![],
+            vec![],
+            &registry(1),
+            &ToolContext::default(),
+        )
+        .await;
+
+    assert_eq!(text, "Gave up after too many tool calls");
+    assert_eq!(usage.totalTokenCount, -1);
+    assert!(conversation.contents.is_empty());
+    assert_eq!(mock.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn admin_tools_are_checked() {
+    let registry = registry(5);
+    let user = ToolContext::default();
+    let admin = ToolContext {
+        is_admin: true,
+        ..Default::default()
+    };
+    let call = FunctionCall {
+        name: String::from("secret"),
+        args: json!({}),
+    };
+
+    assert_eq!(
+        registry.declarations(&user)[0].functionDeclarations.len(),
+        1
+    );
+    assert_eq!(
+        registry.declarations(&admin)[0].functionDeclarations.len(),
+        2
+    );
+    assert_eq!(
+        registry.call(&user, &call).await.response["error"],
+        "Unknown tool: secret"
+    );
+    assert_eq!(registry.call(&admin, &call).await.response["result"], "42");
+}
+
+#[tokio::test]
+async fn disabled_tools_are_not_offered() {
+    let mut registry = ToolRegistry::new(vec![String::from("echo")], 5);
+    registry.register(Echo);
+
+    assert!(registry.declarations(&ToolContext::default()).is_empty());
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::turn;
+use rust_discord_bot::transcript::{
+    export, parse_transcript, to_html, to_json, to_markdown, Format,
+};
+
+#[test]
+fn formats_are_parsed() {
+    assert_eq!(Format::parse(""), Some(Format::Markdown));
+    assert_eq!(Format::parse("MD"), Some(Format::Markdown));
+    assert_eq!(Format::parse("json"), Some(Format::Json));
+    assert_eq!(Format::parse("html"), Some(Format::Html));
+    assert_eq!(Format::parse("pdf"), None);
+}
+
+#[test]
+fn markdown_lists_the_turns() {
+    let contents = vec![turn("user", "alice: hi"), turn("model", "Hello!")];
+
+    assert_eq!(
+        to_markdown(&contents),
+        "# Conversation\n\n**User**\n\nalice: hi\n\n**Gemini**\n\nHello!\n"
+    );
+}
+
+#[test]
+fn html_is_escaped() {
+    let html = to_html(&[turn("user", "<script>alert('x')</script> & co")]);
+
+    assert!(html.contains(
+        "<div class=\"turn user\"><b>User</b>\n&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt; &amp; co</div>"
+    ));
+    assert!(!html.contains("<script>"));
+}
+
+#[test]
+fn json_export_round_trips() {
+    let contents = vec![turn("user", "hi"), turn("model", "hello")];
+
+    let imported = parse_transcript(&to_json(&contents)).unwrap();
+
+    assert_eq!(imported.len(), 2);
+    assert_eq!(imported[0].role, "user");
+    assert_eq!(imported[1].text(), "hello");
+    // a bare list of turns works too
+    let list = r#"[{"role": "user", "parts": [{"text": "hi"}]}, {"role": "model", "parts": [{"text": "yo"}]}]"#;
+    assert_eq!(parse_transcript(list).unwrap().len(), 2);
+}
+
+#[test]
+fn invalid_transcripts_are_rejected() {
+    let cases = [
+        ("not json", "The file isn't valid JSON"),
+        (r#"{"turns": []}"#, "The file isn't a transcript"),
+        (r#"{"contents": []}"#, "The transcript has no turns"),
+        (
+            r#"[{"role": "system", "parts": [{"text": "hi"}]}]"#,
+            "Turn 1 has the role \"system\", only user and model are allowed",
+        ),
+        (
+            r#"[{"role": "user", "parts": [{"text": "a"}]}, {"role": "user", "parts": [{"text": "b"}]}]"#,
+            "Turn 2 should be from the model, turns alternate starting with the user",
+        ),
+        (
+            r#"[{"role": "user", "parts": [{"inlineData": {"mimeType": "image/png", "data": ""}}]}]"#,
+            "Turn 1 has to be text",
+        ),
+        (
+            r#"[{"role": "user", "parts": [{"text": "hi"}]}]"#,
+            "The transcript has to end with a reply from the model",
+        ),
+    ];
+    for (text, error) in cases {
+        let result = parse_transcript(text);
+        assert!(
+            result.as_ref().is_err_and(|e| e.starts_with(error)),
+            "{}: {:?}",
+            text,
+            result.map(|contents| contents.len())
+        );
+    }
+}
+
+#[test]
+fn export_starts_at_the_first_question() {
+    // delete_old dropped the question of the first reply
+    let contents = vec![
+        turn("model", "orphan"),
+        turn("user", "hi"),
+        turn("model", "hello"),
+    ];
+
+    let imported = parse_transcript(&export(&contents, Format::Json)).unwrap();
+
+    assert_eq!(imported.len(), 2);
+    assert_eq!(imported[0].text(), "hi");
+    assert!(!export(&contents, Format::Markdown).contains("orphan"));
+}
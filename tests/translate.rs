@@ -0,0 +1,12 @@
+use rust_discord_bot::translate::flag_language;
+
+#[test]
+fn flags_are_mapped_to_languages() {
+    assert_eq!(flag_language("🇫🇷"), Some("French"));
+    assert_eq!(flag_language("🇯🇵"), Some("Japanese"));
+    assert_eq!(flag_language("🇺🇸"), Some("English"));
+    // not a country the bot knows
+    assert_eq!(flag_language("🇦🇶"), None);
+    assert_eq!(flag_language("👍"), None);
+    assert_eq!(flag_language("🇫"), None);
+}
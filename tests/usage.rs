@@ -0,0 +1,25 @@
+use rust_discord_bot::usage::{UsageKey, UsageStore};
+
+fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("{}-{}.json", name, std::process::id()));
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn records_are_saved_by_flush() {
+    let path = temp_path("usage-flush");
+    let mut store = UsageStore::load(&path);
+    let key = UsageKey {
+        user_id: 1,
+        channel_id: 2,
+        guild_id: 3,
+    };
+
+    store.record("2024-08-21", key, "gemini-1.5-flash-001", 10, 5);
+    assert!(UsageStore::load(&path).records.is_empty());
+    store.flush();
+    let saved = UsageStore::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(saved.records.len(), 1);
+    assert_eq!(saved.records[0].prompt_tokens, 10);
+}